@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+/// An address range announced by one autonomous system.
+struct Network {
+    start: u128,
+    end: u128,
+    number: u32,
+    name: String,
+}
+
+/// Ranges sorted by start address. IPv4 ranges are stored as IPv4-mapped
+/// IPv6 so both families share one table.
+static NETWORKS: OnceLock<Vec<Network>> = OnceLock::new();
+
+/// Loads an IP-to-ASN table in the tab-separated iptoasn.com layout:
+/// `range_start  range_end  AS_number  country_code  AS_description`.
+/// Returns how many ranges were loaded. Rows for AS 0 (unrouted space) and
+/// rows that do not parse are skipped.
+pub fn load(path: &str) -> io::Result<usize> {
+    let mut networks = Vec::new();
+    let mut skipped = 0;
+
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_row(&line) {
+            Some(network) if network.number != 0 => networks.push(network),
+            Some(_) => {}
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        log_warn!("skipped {} malformed rows in {}", skipped, path);
+    }
+
+    networks.sort_by_key(|network| network.start);
+    let loaded = networks.len();
+    let _ = NETWORKS.set(networks);
+    Ok(loaded)
+}
+
+fn parse_row(line: &str) -> Option<Network> {
+    let mut fields = line.split('\t');
+    let start = key(fields.next()?.parse().ok()?);
+    let end = key(fields.next()?.parse().ok()?);
+    let number = fields.next()?.trim_start_matches("AS").parse().ok()?;
+    let _country = fields.next();
+    let name = fields.next().unwrap_or("").trim().to_string();
+    (start <= end).then_some(Network { start, end, number, name })
+}
+
+fn key(address: IpAddr) -> u128 {
+    match address {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// Text to put after an address, such as ` [AS15169 GOOGLE]`. Empty when no
+/// table is loaded or the address is not covered by it.
+pub fn annotate(address: IpAddr) -> String {
+    match lookup(address) {
+        Some(network) if network.name.is_empty() => format!(" [AS{}]", network.number),
+        Some(network) => format!(" [AS{} {}]", network.number, network.name),
+        None => String::new(),
+    }
+}
+
+/// The AS number announcing `address`, if the loaded table knows it.
+pub fn number(address: IpAddr) -> Option<u32> {
+    lookup(address).map(|network| network.number)
+}
+
+fn lookup(address: IpAddr) -> Option<&'static Network> {
+    let networks = NETWORKS.get()?;
+    let address = key(address);
+    let after = networks.partition_point(|network| network.start <= address);
+    let network = &networks[after.checked_sub(1)?];
+    (address <= network.end).then_some(network)
+}
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use socket2::Socket;
+
+use crate::packet::IcmpError;
+use crate::{
+    create_icmp_packet, create_socket, is_timeout, json, next_sequence, output, probe_error, report_error,
+    send_and_receive_ring, Options, SocketKind,
+};
+
+/// Initial TTLs in common use; a reply is assumed to have started at the
+/// smallest one that is not below the TTL it arrived with.
+const INITIAL_TTLS: [u8; 4] = [32, 64, 128, 255];
+
+/// How one echo request sent with a given TTL ended.
+pub enum Outcome {
+    Reply { rtt: Duration, ttl: Option<u8> },
+    Expired { from: IpAddr, rtt: Duration },
+    /// A router or the target answered with another ICMP error, such as
+    /// net or host unreachable.
+    Unreachable { from: IpAddr },
+    Silent,
+}
+
+/// Estimates the hop count of the path to `target` and of the path back.
+///
+/// Forward: the smallest TTL whose echo request still reaches the target,
+/// found by bisecting between 1 and `-ttl`. Reverse: the TTL the reply
+/// arrived with, subtracted from the initial TTL the target most likely
+/// used. Both are heuristics; silent routers, load balancing and unusual
+/// initial TTLs all skew them.
+pub fn run_asymmetry(target: IpAddr, options: &Options) {
+    let mut prober = match Prober::open(target, options) {
+        Ok(prober) => prober,
+        Err(e) => {
+            report_error(json::error("socket", target, &e), format!("Failed to create socket: {}", e));
+            return;
+        }
+    };
+    let max_ttl = options.ttl.clamp(1, 255) as u8;
+
+    // The full-TTL echo gives the reverse estimate and bounds the search.
+    let Some((rtt, reply_ttl)) = (0..options.count.unwrap_or(1).max(1)).find_map(|_| match prober.probe(max_ttl, options) {
+        Outcome::Reply { rtt, ttl } => Some((rtt, ttl)),
+        _ => None,
+    }) else {
+        say!("No echo reply from {} with TTL {}; the paths cannot be estimated.", target, max_ttl);
+        return;
+    };
+    say!("Echo reply with TTL {}: time={}", max_ttl, output::rtt(rtt));
+
+    // Find the smallest TTL that still gets a reply. TTLs that expire or go
+    // unanswered are taken to fall short of the target.
+    let (mut low, mut high) = (0u8, max_ttl);
+    let mut last_router = None;
+    while high - low > 1 {
+        let ttl = low + (high - low) / 2;
+        match prober.probe(ttl, options) {
+            Outcome::Reply { .. } => high = ttl,
+            Outcome::Expired { from, rtt } => {
+                say!("    TTL {:>3}: expired at {} time={}", ttl, from, output::rtt(rtt));
+                // `low` only grows, so the latest expiry is the router nearest the target.
+                last_router = Some((ttl, from, rtt));
+                low = ttl;
+            }
+            Outcome::Unreachable { from } => {
+                say!("    TTL {:>3}: unreachable, reported by {}", ttl, from);
+                low = ttl;
+            }
+            Outcome::Silent => {
+                say!("    TTL {:>3}: no answer", ttl);
+                low = ttl;
+            }
+        }
+    }
+    let forward = high - 1;
+
+    say!("\nPath asymmetry estimate for {} (heuristic):", target);
+    say!("    Forward: ~{} hops (the echo request first reached the target with TTL {})", forward, high);
+    if let Some((ttl, from, router_rtt)) = last_router.filter(|(ttl, _, _)| *ttl == forward) {
+        say!(
+            "    Last router before the target: {} at TTL {}, time={} ({} to the target)",
+            from,
+            ttl,
+            output::rtt(router_rtt),
+            output::rtt_delta(rtt.as_secs_f64() - router_rtt.as_secs_f64())
+        );
+    }
+    let Some(reply_ttl) = reply_ttl else {
+        say!("    Reverse: unknown; this socket does not report the TTL replies arrive with");
+        return;
+    };
+    let initial = INITIAL_TTLS.into_iter().find(|initial| *initial >= reply_ttl).unwrap_or(255);
+    let reverse = initial - reply_ttl;
+    say!("    Reverse: ~{} hops (reply arrived with TTL {}, assuming it started at {})", reverse, reply_ttl, initial);
+
+    match forward.cmp(&reverse) {
+        std::cmp::Ordering::Equal => say!("    The paths look symmetric in hop count."),
+        _ => say!(
+            "    The paths likely differ: {} hops out, {} back ({:+} on the way back).",
+            forward,
+            reverse,
+            i16::from(reverse) - i16::from(forward)
+        ),
+    }
+}
+
+/// Sends echo requests to one target with a TTL chosen per probe.
+pub struct Prober {
+    socket: Socket,
+    kind: SocketKind,
+    target: IpAddr,
+    sequence: u16,
+    recent_ids: VecDeque<u16>,
+}
+
+impl Prober {
+    pub fn open(target: IpAddr, options: &Options) -> io::Result<Prober> {
+        let (socket, kind) = create_socket(target, options)?;
+        Ok(Prober { socket, kind, target, sequence: 0, recent_ids: VecDeque::from([std::process::id() as u16]) })
+    }
+
+    pub fn probe(&mut self, ttl: u8, options: &Options) -> Outcome {
+        if let Err(e) = self.set_ttl(ttl) {
+            log_warn!("failed to set TTL {} for a probe to {}: {}", ttl, self.target, e);
+            return Outcome::Silent;
+        }
+        self.sequence = next_sequence(self.sequence, options);
+        let packet = create_icmp_packet(options.packet_size, self.target, self.recent_ids[0], self.sequence, None, false);
+        let dest_addr = SocketAddr::new(self.target, 0);
+
+        let started = std::time::Instant::now();
+        match send_and_receive_ring(&self.socket, self.kind, &packet, &dest_addr, &self.recent_ids, options) {
+            Ok(reply) => Outcome::Reply { rtt: reply.rtt, ttl: reply.ttl },
+            Err(e) => match probe_error(&e) {
+                Some(error) if error.error == IcmpError::TtlExceeded => Outcome::Expired { from: error.from, rtt: started.elapsed() },
+                Some(error) => Outcome::Unreachable { from: error.from },
+                None => {
+                    if !is_timeout(&e) {
+                        log_warn!("probe to {} with TTL {} failed: {}", self.target, ttl, e);
+                    }
+                    Outcome::Silent
+                }
+            },
+        }
+    }
+
+    fn set_ttl(&self, ttl: u8) -> io::Result<()> {
+        match self.target {
+            IpAddr::V4(_) => self.socket.set_ttl(u32::from(ttl)),
+            IpAddr::V6(_) => self.socket.set_unicast_hops_v6(u32::from(ttl)),
+        }
+    }
+}
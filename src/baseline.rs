@@ -0,0 +1,137 @@
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::{output, PingStatistics};
+
+const HEADER: &str = "# ring baseline v1";
+
+/// The figures of a finished run that later runs are compared against.
+pub struct Baseline {
+    pub target: String,
+    pub size: usize,
+    pub sent: u32,
+    pub received: u32,
+    pub loss_percent: f64,
+    pub avg_ms: Option<f64>,
+    pub median_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+}
+
+impl Baseline {
+    pub fn from_stats(target: IpAddr, size: usize, stats: &PingStatistics) -> Baseline {
+        let millis = |rtt: std::time::Duration| rtt.as_secs_f64() * 1000.0;
+        Baseline {
+            target: target.to_string(),
+            size,
+            sent: stats.sent,
+            received: stats.received,
+            loss_percent: stats.loss_percent(),
+            avg_ms: stats.average_rtt().map(millis),
+            median_ms: stats.samples.percentile(50.0).map(millis),
+            p95_ms: stats.samples.percentile(95.0).map(millis),
+        }
+    }
+
+    /// Writes the baseline as `key value` lines; RTTs a run without replies
+    /// does not have are left out.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut text = format!(
+            "{}\ntarget {}\nsize {}\nsent {}\nreceived {}\nloss_percent {:.3}\n",
+            HEADER, self.target, self.size, self.sent, self.received, self.loss_percent
+        );
+        for (key, value) in [("avg_ms", self.avg_ms), ("median_ms", self.median_ms), ("p95_ms", self.p95_ms)] {
+            if let Some(value) = value {
+                text.push_str(&format!("{} {:.3}\n", key, value));
+            }
+        }
+        fs::write(path, text)
+    }
+
+    pub fn load(path: &str) -> io::Result<Baseline> {
+        let text = fs::read_to_string(path)?;
+        if text.lines().next() != Some(HEADER) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a ring baseline file (--save-baseline writes one)"));
+        }
+
+        let mut baseline = Baseline {
+            target: String::new(),
+            size: 0,
+            sent: 0,
+            received: 0,
+            loss_percent: 0.0,
+            avg_ms: None,
+            median_ms: None,
+            p95_ms: None,
+        };
+        for line in text.lines().skip(1) {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid {} in baseline: {}", key, value));
+            match key {
+                "target" => baseline.target = value.to_string(),
+                "size" => baseline.size = value.parse().map_err(|_| invalid())?,
+                "sent" => baseline.sent = value.parse().map_err(|_| invalid())?,
+                "received" => baseline.received = value.parse().map_err(|_| invalid())?,
+                "loss_percent" => baseline.loss_percent = value.parse().map_err(|_| invalid())?,
+                "avg_ms" => baseline.avg_ms = Some(value.parse().map_err(|_| invalid())?),
+                "median_ms" => baseline.median_ms = Some(value.parse().map_err(|_| invalid())?),
+                "p95_ms" => baseline.p95_ms = Some(value.parse().map_err(|_| invalid())?),
+                _ => {}
+            }
+        }
+        Ok(baseline)
+    }
+}
+
+/// Prints how `current` differs from `baseline`. With `max_ms`, also returns
+/// why the run counts as a regression, if it does: it got no replies while
+/// the baseline did, or its average RTT rose by more than `max_ms`.
+pub fn compare(current: &Baseline, baseline: &Baseline, path: &str, max_ms: Option<f64>) -> Option<String> {
+    say!(
+        "\nCompared with baseline {} ({}, -s {}, {} probes):",
+        path, baseline.target, baseline.size, baseline.sent
+    );
+    if current.target != baseline.target || current.size != baseline.size {
+        say!("    Note: the baseline was taken against {} with -s {}", baseline.target, baseline.size);
+    }
+    for (name, now, then) in [
+        ("avg RTT", current.avg_ms, baseline.avg_ms),
+        ("median RTT", current.median_ms, baseline.median_ms),
+        ("p95 RTT", current.p95_ms, baseline.p95_ms),
+    ] {
+        match (now, then) {
+            (Some(now), Some(then)) => say!("    {} {} vs {} ({})", name, rtt(now), rtt(then), delta(now - then)),
+            (None, Some(then)) => say!("    {} - vs {}", name, rtt(then)),
+            (Some(now), None) => say!("    {} {} vs -", name, rtt(now)),
+            (None, None) => {}
+        }
+    }
+    say!(
+        "    loss {:.0}% vs {:.0}% ({:+.0} points)",
+        current.loss_percent,
+        baseline.loss_percent,
+        current.loss_percent - baseline.loss_percent
+    );
+
+    let max_ms = max_ms?;
+    match (current.avg_ms, baseline.avg_ms) {
+        (None, Some(_)) => Some("no replies, while the baseline run had some".to_string()),
+        (Some(now), Some(then)) if now - then > max_ms => {
+            Some(format!("avg RTT rose by {}, more than the allowed {}ms", rtt(now - then), max_ms))
+        }
+        _ => None,
+    }
+}
+
+/// A baseline RTT, stored in milliseconds, in the `--unit` unit.
+fn rtt(ms: f64) -> String {
+    output::rtt(Duration::from_secs_f64(ms.max(0.0) / 1000.0))
+}
+
+/// A signed difference of two baseline RTTs in the `--unit` unit.
+fn delta(ms: f64) -> String {
+    output::rtt_delta(ms / 1000.0)
+}
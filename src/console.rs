@@ -0,0 +1,138 @@
+use std::io::{self, IsTerminal};
+use std::time::Instant;
+
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+/// Terminal settings in effect before the console took over, restored on
+/// drop or when the run is interrupted with Ctrl-C.
+#[cfg(unix)]
+static SAVED: OnceLock<libc::termios> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Key {
+    Pause,
+    Resume,
+    Stats,
+    Quit,
+}
+
+impl Key {
+    fn from_byte(byte: u8) -> Option<Key> {
+        match byte {
+            b'p' => Some(Key::Pause),
+            b'r' => Some(Key::Resume),
+            b's' => Some(Key::Stats),
+            b'q' => Some(Key::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Reads single-key commands from stdin without waiting for Enter.
+pub struct Console {
+    _private: (),
+}
+
+impl Console {
+    /// Switches the terminal to non-canonical, non-echoing input. Returns
+    /// `None` when stdin is not a terminal.
+    pub fn open() -> Option<Console> {
+        if !io::stdin().is_terminal() {
+            return None;
+        }
+
+        #[cfg(unix)]
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return None;
+            }
+
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+
+            let _ = SAVED.set(saved);
+            libc::signal(libc::SIGINT, restore_and_reraise as extern "C" fn(libc::c_int) as libc::sighandler_t);
+            Some(Console { _private: () })
+        }
+
+        #[cfg(not(unix))]
+        None
+    }
+
+    /// Waits for a recognised key until `deadline`, or indefinitely when no
+    /// deadline is given. Returns `None` on timeout or when stdin closes.
+    pub fn next_key(&self, deadline: Option<Instant>) -> Option<Key> {
+        #[cfg(unix)]
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return None;
+                    }
+                    remaining.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+                }
+                None => -1,
+            };
+
+            let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+            let ready = unsafe { libc::poll(&mut fd, 1, timeout) };
+            if ready < 0 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return None;
+            }
+            if ready == 0 {
+                return None;
+            }
+
+            let mut byte = 0u8;
+            let read = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if read <= 0 {
+                return None;
+            }
+            if let Some(key) = Key::from_byte(byte) {
+                return Some(key);
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = deadline;
+            None
+        }
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(saved) = SAVED.get() {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+                libc::signal(libc::SIGINT, libc::SIG_DFL);
+            }
+        }
+    }
+}
+
+/// Puts the terminal back before letting the default SIGINT action end the
+/// process, so Ctrl-C does not leave the shell without echo.
+#[cfg(unix)]
+extern "C" fn restore_and_reraise(signal: libc::c_int) {
+    unsafe {
+        if let Some(saved) = SAVED.get() {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
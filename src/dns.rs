@@ -0,0 +1,223 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// DNS servers given with `--resolver`, tried in order.
+static RESOLVERS: OnceLock<Vec<SocketAddr>> = OnceLock::new();
+
+const DNS_PORT: u16 = 53;
+const HEADER_LEN: usize = 12;
+/// Without EDNS, a UDP answer is at most 512 bytes.
+const MAX_UDP_ANSWER: usize = 512;
+/// Compression pointers allowed in one name, which stops pointer loops.
+const MAX_POINTERS: usize = 16;
+
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+pub fn set_resolvers(resolvers: Vec<SocketAddr>) {
+    let _ = RESOLVERS.set(resolvers);
+}
+
+/// Whether names are resolved through `--resolver` instead of the system
+/// resolver.
+pub fn has_resolvers() -> bool {
+    RESOLVERS.get().is_some_and(|resolvers| !resolvers.is_empty())
+}
+
+/// Parses `192.0.2.53`, `192.0.2.53:5353`, `2001:db8::53` or `[2001:db8::53]:5353`.
+pub fn parse_resolver(value: &str) -> Option<SocketAddr> {
+    value
+        .parse::<SocketAddr>()
+        .ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, DNS_PORT)))
+}
+
+/// One address record from an answer.
+pub struct Record {
+    pub address: IpAddr,
+    pub ttl: u32,
+}
+
+impl Record {
+    pub fn kind(&self) -> &'static str {
+        if self.address.is_ipv4() {
+            "A"
+        } else {
+            "AAAA"
+        }
+    }
+}
+
+/// The addresses one resolver returned for a name.
+pub struct Answer {
+    pub resolver: SocketAddr,
+    /// Names the query was redirected through, in order.
+    pub cnames: Vec<String>,
+    pub records: Vec<Record>,
+}
+
+/// Looks `name` up through each `--resolver` in turn, asking for both A and
+/// AAAA records, until one returns an address. Failures of the earlier
+/// resolvers are reported as the next one is tried.
+pub fn resolve(name: &str, timeout: Duration) -> Result<Answer, String> {
+    let resolvers = RESOLVERS.get().map(Vec::as_slice).unwrap_or_default();
+    let mut last_error = String::from("no --resolver given");
+    for (index, resolver) in resolvers.iter().enumerate() {
+        match resolve_with(*resolver, name, timeout) {
+            Ok(answer) if !answer.records.is_empty() => return Ok(answer),
+            Ok(_) => last_error = format!("{} has no A or AAAA records at {}", name, resolver),
+            Err(e) => last_error = format!("resolver {} failed for {}: {}", resolver, name, e),
+        }
+        log_warn!("{}", last_error);
+        if index + 1 < resolvers.len() {
+            say!("Note: {}; trying {}.", last_error, resolvers[index + 1]);
+        }
+    }
+    Err(last_error)
+}
+
+fn resolve_with(resolver: SocketAddr, name: &str, timeout: Duration) -> Result<Answer, String> {
+    let bind = match resolver {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(bind).map_err(|e| e.to_string())?;
+    socket.connect(resolver).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+
+    let mut answer = Answer { resolver, cnames: Vec::new(), records: Vec::new() };
+    for record_type in [TYPE_A, TYPE_AAAA] {
+        match query(&socket, name, record_type, timeout, &mut answer) {
+            Ok(()) => {}
+            // Some servers mishandle AAAA queries; the A records still count.
+            Err(e) if !answer.records.is_empty() => log_debug!("AAAA lookup of {} at {} failed: {}", name, resolver, e),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(answer)
+}
+
+fn query(socket: &UdpSocket, name: &str, record_type: u16, timeout: Duration, answer: &mut Answer) -> Result<(), String> {
+    let id: u16 = rand::random();
+    socket.send(&build_query(id, name, record_type)?).map_err(|e| e.to_string())?;
+
+    let mut buffer = [0u8; MAX_UDP_ANSWER];
+    let received = loop {
+        let len = socket.recv(&mut buffer).map_err(|e| match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                format!("no answer within {}ms", timeout.as_millis())
+            }
+            _ => e.to_string(),
+        })?;
+        // A stale answer to an earlier query is skipped.
+        if len >= 2 && u16::from_be_bytes([buffer[0], buffer[1]]) == id {
+            break len;
+        }
+    };
+    parse_answer(&buffer[..received], answer)
+}
+
+/// A recursive query for one name and record type.
+fn build_query(id: u16, name: &str, record_type: u16) -> Result<Vec<u8>, String> {
+    let mut query = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&[0x01, 0x00]); // Recursion desired
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // One question
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("{} is not a valid DNS name", name));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Adds the A, AAAA and CNAME records of a response to `answer`.
+fn parse_answer(message: &[u8], answer: &mut Answer) -> Result<(), String> {
+    let truncated = || "the answer is truncated".to_string();
+    if message.len() < HEADER_LEN || message[2] & 0x80 == 0 {
+        return Err("not a DNS response".to_string());
+    }
+    match message[3] & 0x0f {
+        0 => {}
+        // NXDOMAIN: the name does not exist as far as this resolver knows.
+        3 => return Err("no such name (NXDOMAIN)".to_string()),
+        code => return Err(format!("the resolver answered with error code {}", code)),
+    }
+    if message[2] & 0x02 != 0 {
+        log_warn!("DNS answer from {} was truncated; using the records that fit", answer.resolver);
+    }
+
+    let questions = u16::from_be_bytes([message[4], message[5]]);
+    let answers = u16::from_be_bytes([message[6], message[7]]);
+    let mut offset = HEADER_LEN;
+    for _ in 0..questions {
+        offset = read_name(message, offset).ok_or_else(truncated)?.1 + 4;
+    }
+    for _ in 0..answers {
+        let (_, end) = read_name(message, offset).ok_or_else(truncated)?;
+        let fixed = message.get(end..end + 10).ok_or_else(truncated)?;
+        let record_type = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let length = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let data_start = end + 10;
+        let data = message.get(data_start..data_start + length).ok_or_else(truncated)?;
+
+        match (record_type, length) {
+            (TYPE_A, 4) => {
+                let address = IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+                answer.records.push(Record { address, ttl });
+            }
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = data.try_into().unwrap();
+                answer.records.push(Record { address: IpAddr::V6(Ipv6Addr::from(octets)), ttl });
+            }
+            (TYPE_CNAME, _) => {
+                if let Some((cname, _)) = read_name(message, data_start) {
+                    if !answer.cnames.contains(&cname) {
+                        answer.cnames.push(cname);
+                    }
+                }
+            }
+            _ => {}
+        }
+        offset = data_start + length;
+    }
+    Ok(())
+}
+
+/// Reads the possibly compressed name at `offset`; returns it with the
+/// offset just past it in the message.
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *message.get(offset)? as usize;
+        match length {
+            0 => {
+                return Some((labels.join("."), end.unwrap_or(offset + 1)));
+            }
+            _ if length & 0xc0 == 0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                offset = (length & 0x3f) << 8 | *message.get(offset + 1)? as usize;
+            }
+            _ => {
+                let label = message.get(offset + 1..offset + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + length;
+            }
+        }
+    }
+}
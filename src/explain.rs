@@ -0,0 +1,91 @@
+use std::net::IpAddr;
+
+use crate::{base_identifier, create_icmp_packet, echo_header, initial_sequence, payload_rng, Options, ICMP_HEADER_LEN};
+
+const HEX_ROW: usize = 16;
+
+/// Prints the first echo request a run against `target` would send, field by
+/// field, followed by a scapy line that builds the same packet.
+///
+/// The identifier and sequence number are the ones `run_ring` starts with
+/// (`--id` and `--start-seq` included) and the payload comes from the same
+/// generator, so with `--seed` or `--zero-payload` the bytes match what goes
+/// on the wire exactly.
+pub fn explain(target: IpAddr, options: &Options) {
+    let packet = create_icmp_packet(
+        options.packet_size,
+        target,
+        base_identifier(options),
+        initial_sequence(options).wrapping_add(1),
+        payload_rng(options).as_mut(),
+        options.payload_crc,
+    );
+    let (identifier, sequence) = echo_header(&packet);
+    let checksum = u16::from_be_bytes([packet[2], packet[3]]);
+    let payload = &packet[ICMP_HEADER_LEN..];
+    let family = if target.is_ipv4() { "ICMPv4" } else { "ICMPv6" };
+
+    println!("Echo request to {} ({}, {} bytes):", target, family, packet.len());
+    println!("    {:>6}  {:<8}  field", "offset", "bytes");
+    field(0, &packet[0..1], format!("type        {} (echo request)", packet[0]));
+    field(1, &packet[1..2], format!("code        {}", packet[1]));
+    field(2, &packet[2..4], format!("checksum    0x{:04x}", checksum));
+    field(4, &packet[4..6], format!("identifier  {} (0x{:04x})", identifier, identifier));
+    field(6, &packet[6..8], format!("sequence    {}", sequence));
+    if !payload.is_empty() {
+        println!("    {:>6}  {:<8}  payload     {} bytes", ICMP_HEADER_LEN, "...", payload.len());
+        for (row, chunk) in payload.chunks(HEX_ROW).enumerate() {
+            println!("        {:04x}  {}", ICMP_HEADER_LEN + row * HEX_ROW, hex(chunk, " "));
+        }
+    }
+
+    if options.payload_crc && payload.len() >= crate::packet::CRC_LEN {
+        println!("    The last {} payload bytes are the --payload-crc trailer.", crate::packet::CRC_LEN);
+    }
+    if target.is_ipv6() {
+        println!("    The kernel recomputes ICMPv6 checksums over a pseudo-header, so the one sent will differ.");
+    }
+    if !options.zero_payload && options.seed.is_none() && !payload.is_empty() {
+        println!("    The payload is random on every run; pass --seed to reproduce it.");
+    }
+    println!("    Unprivileged datagram sockets replace the identifier with one the kernel picks.");
+
+    // Scapy writes both fields big-endian, so it gets the values that put
+    // ring's bytes on the wire.
+    let wire_identifier = u16::from_be_bytes([packet[4], packet[5]]);
+    let wire_sequence = u16::from_be_bytes([packet[6], packet[7]]);
+    if (wire_identifier, wire_sequence) != (identifier, sequence) {
+        println!("    --id-endian little swaps both fields; the scapy line below carries the swapped values.");
+    }
+
+    println!("scapy:");
+    println!("    {}", scapy(target, options, wire_identifier, wire_sequence, payload));
+}
+
+fn field(offset: usize, bytes: &[u8], description: String) {
+    println!("    {:>6}  {:<8}  {}", offset, hex(bytes, " "), description);
+}
+
+fn hex(bytes: &[u8], separator: &str) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(separator)
+}
+
+fn scapy(target: IpAddr, options: &Options, identifier: u16, sequence: u16, payload: &[u8]) -> String {
+    let data = if payload.iter().all(|byte| *byte == 0) {
+        format!("b\"\\x00\" * {}", payload.len())
+    } else {
+        format!("bytes.fromhex(\"{}\")", hex(payload, ""))
+    };
+    let timeout = (options.timeout as f64 / 1000.0).max(0.001);
+
+    match target {
+        IpAddr::V4(_) => format!(
+            "sr1(IP(dst=\"{}\", ttl={})/ICMP(type=8, code=0, id=0x{:04x}, seq={})/Raw(load={}), timeout={})",
+            target, options.ttl, identifier, sequence, data, timeout
+        ),
+        IpAddr::V6(_) => format!(
+            "sr1(IPv6(dst=\"{}\", hlim={})/ICMPv6EchoRequest(id=0x{:04x}, seq={}, data={}), timeout={})",
+            target, options.ttl, identifier, sequence, data, timeout
+        ),
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+/// Default `--histogram` bucket boundaries, in milliseconds.
+pub const DEFAULT_BOUNDS: [f64; 10] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0];
+
+const BAR_WIDTH: usize = 40;
+
+/// Parses `--histogram-buckets` such as `1,2,5,10`: strictly increasing,
+/// positive upper bounds in milliseconds. The first bucket starts at 0 and
+/// an open-ended bucket follows the last bound.
+pub fn parse_bounds(value: &str) -> Option<Vec<f64>> {
+    let bounds: Vec<f64> = value.split(',').map(|bound| bound.trim().parse().ok()).collect::<Option<_>>()?;
+    let increasing = bounds.windows(2).all(|pair| pair[0] < pair[1]);
+    let valid = bounds.iter().all(|bound| bound.is_finite() && *bound > 0.0);
+    (!bounds.is_empty() && increasing && valid).then_some(bounds)
+}
+
+/// Renders `samples` as one line per bucket with its count and a bar scaled
+/// to the fullest bucket. Empty buckets before the first and after the last
+/// populated one are left out.
+pub fn render(samples: &[Duration], bounds: &[f64]) -> Vec<String> {
+    let mut counts = vec![0usize; bounds.len() + 1];
+    for rtt in samples {
+        let millis = rtt.as_secs_f64() * 1000.0;
+        counts[bounds.partition_point(|bound| *bound <= millis)] += 1;
+    }
+
+    let Some(first) = counts.iter().position(|count| *count > 0) else {
+        return Vec::new();
+    };
+    let last = counts.iter().rposition(|count| *count > 0).unwrap_or(first);
+    let fullest = counts.iter().copied().max().unwrap_or(1);
+
+    let labels: Vec<String> = (first..=last).map(|index| label(bounds, index)).collect();
+    let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+
+    labels
+        .iter()
+        .zip(&counts[first..=last])
+        .map(|(label, count)| {
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(fullest));
+            format!("    {:>width$} |{:<bar_width$} {}", label, bar, count, width = label_width, bar_width = BAR_WIDTH)
+        })
+        .collect()
+}
+
+fn label(bounds: &[f64], index: usize) -> String {
+    match index {
+        0 => format!("0-{}ms", bounds[0]),
+        index if index == bounds.len() => format!(">={}ms", bounds[index - 1]),
+        index => format!("{}-{}ms", bounds[index - 1], bounds[index]),
+    }
+}
@@ -0,0 +1,252 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::{asn, PingStatistics, ProbeError, Reply};
+
+enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+static TAG: OnceLock<String> = OnceLock::new();
+
+/// Labels every record created from now on with `"tag"` (`--tag`).
+pub fn set_tag(tag: &str) {
+    let _ = TAG.set(tag.to_string());
+}
+
+/// A flat JSON object whose fields keep their insertion order.
+pub struct Object {
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Object {
+    pub fn new(kind: &str) -> Object {
+        let object = Object { fields: Vec::new() }.str("type", kind).str("time", crate::logger::timestamp());
+        match TAG.get() {
+            Some(tag) => object.str("tag", tag),
+            None => object,
+        }
+    }
+
+    pub fn str(mut self, key: &'static str, value: impl ToString) -> Object {
+        self.fields.push((key, Value::Str(value.to_string())));
+        self
+    }
+
+    pub fn int(mut self, key: &'static str, value: impl Into<i64>) -> Object {
+        self.fields.push((key, Value::Int(value.into())));
+        self
+    }
+
+    pub fn float(mut self, key: &'static str, value: f64) -> Object {
+        self.fields.push((key, Value::Float(value)));
+        self
+    }
+
+    pub fn bool(mut self, key: &'static str, value: bool) -> Object {
+        self.fields.push((key, Value::Bool(value)));
+        self
+    }
+
+    pub fn millis(self, key: &'static str, value: Duration) -> Object {
+        self.float(key, value.as_secs_f64() * 1000.0)
+    }
+
+    /// Serialises the object on a single line, as NDJSON expects.
+    pub fn to_line(&self) -> String {
+        self.render(false)
+    }
+
+    /// Serialises the object with one indented field per line.
+    pub fn to_pretty(&self) -> String {
+        self.render(true)
+    }
+
+    fn render(&self, pretty: bool) -> String {
+        let (newline, indent, colon) = if pretty { ("\n", "  ", ": ") } else { ("", "", ":") };
+        let mut out = String::from("{");
+        for (index, (key, value)) in self.fields.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            out.push_str(newline);
+            out.push_str(indent);
+            write_string(&mut out, key);
+            out.push_str(colon);
+            write_value(&mut out, value);
+        }
+        if !self.fields.is_empty() {
+            out.push_str(newline);
+        }
+        out.push('}');
+        out
+    }
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Str(value) => write_string(out, value),
+        Value::Int(value) => {
+            let _ = write!(out, "{}", value);
+        }
+        // JSON has no NaN or infinity.
+        Value::Float(value) if !value.is_finite() => out.push_str("null"),
+        Value::Float(value) => {
+            let _ = write!(out, "{:.3}", value);
+        }
+        Value::Bool(value) => {
+            let _ = write!(out, "{}", value);
+        }
+    }
+}
+
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// One received echo reply. `ttl_warning` is set when `--warn-on-ttl-below`
+/// is in effect.
+pub fn reply(target: IpAddr, reply: &Reply, ttl_warning: Option<bool>) -> Object {
+    let mut record = Object::new("reply")
+        .str("target", target)
+        .str("from", reply.source)
+        .int("seq", reply.sequence)
+        .int("bytes", reply.bytes as i64)
+        .millis("rtt_ms", reply.rtt);
+    if let Some(number) = asn::number(reply.source) {
+        record = record.int("asn", number);
+    }
+    if let Some(ttl) = reply.ttl {
+        record = record.int("ttl", ttl);
+    }
+    if let Some(tos) = reply.tos {
+        record = record.int("tos", tos);
+    }
+    if let Some(intact) = reply.payload_crc {
+        record = record.bool("payload_crc_ok", intact);
+    }
+    if reply.truncated {
+        record = record.bool("truncated", true);
+    }
+    if let Some(send) = reply.send {
+        record = record.millis("send_ms", send);
+    }
+    if let (Some(network), Some(delivery)) = (reply.network_rtt(), reply.delivery) {
+        record = record.millis("network_rtt_ms", network).millis("delivery_ms", delivery);
+    }
+    if let Some(ttl_warning) = ttl_warning {
+        record = record.bool("ttl_warning", ttl_warning);
+    }
+    record
+}
+
+pub fn timeout(target: IpAddr, sequence: u16) -> Object {
+    Object::new("timeout").str("target", target).int("seq", sequence)
+}
+
+/// An ICMP error that answered a probe instead of an echo reply.
+pub fn icmp_error(target: IpAddr, sequence: u16, error: &ProbeError) -> Object {
+    let mut record = Object::new("icmp_error")
+        .str("target", target)
+        .int("seq", sequence)
+        .str("from", error.from)
+        .str("reason", error.error.describe());
+    if let Some(mtu) = error.mtu {
+        record = record.int("mtu", mtu);
+    }
+    match asn::number(error.from) {
+        Some(number) => record.int("asn", number),
+        None => record,
+    }
+}
+
+/// A failure reported in place of the text line it would otherwise print.
+/// `error` names what failed: `resolve`, `socket` (creating one),
+/// `permission` or `probe` (sending or receiving one probe).
+pub fn error(error: &str, host: impl ToString, detail: impl ToString) -> Object {
+    Object::new("error").str("error", error).str("host", host).str("detail", detail)
+}
+
+/// A target that started or stopped answering during a run.
+pub fn state(target: IpAddr, up: bool) -> Object {
+    Object::new("state").str("target", target).str("status", if up { "up" } else { "down" })
+}
+
+/// The baseline a `--warmup-count` run watches later replies against.
+pub fn baseline(target: IpAddr, stats: &PingStatistics, mdev: Duration, threshold: Duration) -> Object {
+    let mut record = Object::new("baseline")
+        .str("target", target)
+        .int("sent", stats.sent)
+        .int("received", stats.received)
+        .float("loss_percent", stats.loss_percent());
+    if let Some(average) = stats.average_rtt() {
+        record = record.millis("avg_ms", average);
+    }
+    record.millis("mdev_ms", mdev).millis("threshold_ms", threshold)
+}
+
+pub fn summary(target: IpAddr, stats: &PingStatistics) -> Object {
+    let mut record = Object::new("summary")
+        .str("target", target)
+        .str("status", if stats.received + stats.late_replies > 0 { "up" } else { "down" })
+        .int("sent", stats.sent)
+        .int("received", stats.received)
+        .float("loss_percent", stats.loss_percent())
+        .str("exit_reason", stats.exit_reason.key());
+    if let Some((sent, received)) = stats.rates() {
+        record = record.millis("elapsed_ms", stats.elapsed).float("send_pps", sent).float("receive_pps", received);
+    }
+    if let Some(mtu) = stats.path_mtu {
+        record = record.int("path_mtu", mtu);
+    }
+    if stats.rx_drops > 0 {
+        record = record.int("rx_drops", stats.rx_drops);
+    }
+    if stats.late_replies > 0 {
+        record = record.int("late_replies", stats.late_replies);
+    }
+    if stats.short_replies > 0 {
+        record = record.int("short_replies", stats.short_replies);
+    }
+    if let Some(send) = stats.average_send() {
+        record = record.millis("avg_send_ms", send).millis("max_send_ms", stats.max_send);
+    }
+    if let Some(delivery) = stats.average_delivery() {
+        record = record.millis("avg_delivery_ms", delivery);
+    }
+    if let Some(threshold) = stats.anomaly_threshold {
+        record = record.millis("anomaly_threshold_ms", threshold).int("anomalies", stats.anomalies);
+    }
+    if stats.crc_mismatches > 0 {
+        record = record.int("crc_mismatches", stats.crc_mismatches);
+    }
+    if stats.reordered > 0 {
+        record = record.int("reordered", stats.reordered).int("duplicates", stats.duplicates);
+    }
+    if let (Some(average), Some((min, max))) = (stats.average_rtt(), stats.rtt_range()) {
+        record = record.millis("min_ms", min).millis("avg_ms", average).millis("max_ms", max);
+    }
+    if let Some(trimmed) = stats.trimmed_mean_rtt() {
+        record = record.millis("trimmed_mean_ms", trimmed);
+    }
+    record
+}
@@ -0,0 +1,123 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn parse(value: &str) -> Option<Level> {
+        match value.to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+enum Sink {
+    File(File),
+    Stderr,
+}
+
+struct Logger {
+    level: Level,
+    sink: Mutex<Sink>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Installs the process-wide logger. Until this is called every record is
+/// discarded, which keeps the default output identical to plain `ring`.
+pub fn init(path: Option<&str>, level: Level) -> io::Result<()> {
+    let sink = match path {
+        Some(path) => Sink::File(OpenOptions::new().create(true).append(true).open(path)?),
+        None => Sink::Stderr,
+    };
+
+    let _ = LOGGER.set(Logger { level, sink: Mutex::new(sink) });
+    Ok(())
+}
+
+pub fn log(level: Level, message: std::fmt::Arguments) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    if level > logger.level {
+        return;
+    }
+
+    let line = format!("{} {:<5} {}\n", timestamp(), level.label(), message);
+    if let Ok(mut sink) = logger.sink.lock() {
+        let _ = match &mut *sink {
+            Sink::File(file) => file.write_all(line.as_bytes()),
+            Sink::Stderr => io::stderr().write_all(line.as_bytes()),
+        };
+    }
+}
+
+/// Formats the current wall-clock time as an RFC 3339 UTC timestamp.
+pub fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let days = (secs / 86_400) as i64;
+    let seconds_of_day = secs % 86_400;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        now.subsec_millis()
+    )
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::logger::log($crate::logger::Level::Error, format_args!($($arg)*)) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::logger::log($crate::logger::Level::Warn, format_args!($($arg)*)) };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::logger::log($crate::logger::Level::Info, format_args!($($arg)*)) };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::logger::log($crate::logger::Level::Debug, format_args!($($arg)*)) };
+}
@@ -1,132 +1,2802 @@
+use std::collections::VecDeque;
 use std::env;
-use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
-use std::time::{Duration, Instant};
-use std::thread::sleep;
-use std::io;
-use rand::Rng;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, sleep};
+use std::io::{self, BufRead, IsTerminal, Write};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use socket2::{Domain, Protocol, Socket, Type};
+use console::{Console, Key};
+use packet::{parse_icmp_error, parse_icmp_reply, Endian, Family, IcmpError, ParsedReply};
+use samples::RttSamples;
+use sparkline::Sparkline;
+use template::ReplyTemplate;
+use transport::Probe;
 
 #[cfg(unix)]
 use std::os::unix::io::AsRawFd;
 
+#[macro_use]
+mod logger;
+#[macro_use]
+mod output;
+mod asn;
+mod asymmetry;
+mod baseline;
+mod console;
+mod dns;
+mod explain;
+mod histogram;
+mod json;
+mod ndjson;
+mod neighbor;
+mod packet;
+mod path;
+mod pcap;
+mod ratelimit;
+mod repeat;
+mod report;
+mod router;
+mod samples;
+mod selftest;
+mod sparkline;
+mod sweep;
+mod syslog;
+mod template;
+mod throttle;
+mod transport;
+mod webhook;
+mod window;
+
 #[cfg(unix)]
 use libc::SOCK_RAW;
 
 #[cfg(windows)]
 const SOCK_RAW: i32 = 3;
 
+/// Exit status when the socket stops accepting probes because privileges
+/// were lost mid-run (`EX_NOPERM` from sysexits.h).
+const EXIT_PERMISSION_DENIED: i32 = 77;
+
+/// Modes ring can run in. A bare `ring <target>` is an implicit `ping` so
+/// existing invocations keep working as new modes are added.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+    Ping,
+    /// Ping every host of a CIDR block (`ring sweep 10.0.0.0/24`).
+    Sweep,
+    /// Trace the path to the target and monitor every hop (`ring trace`).
+    Trace,
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let (command, args) = parse_command(env::args().collect());
+    run_command(command, &args);
+}
+
+/// Splits an explicit subcommand off the argument list, leaving the
+/// remaining arguments in the legacy `ring <target> [options]` shape.
+///
+/// Without one, a CIDR target still means `sweep` and `--path-monitor`
+/// still means `trace`, as they did before the subcommands existed.
+fn parse_command(mut args: Vec<String>) -> (Command, Vec<String>) {
+    let explicit = match args.get(1).map(String::as_str) {
+        Some("ping") => Some(Command::Ping),
+        Some("sweep") => Some(Command::Sweep),
+        Some("trace") => Some(Command::Trace),
+        _ => None,
+    };
+    if explicit.is_some() {
+        args.remove(1);
+    }
+
+    let cidr_target = args.get(1).is_some_and(|target| sweep::parse_cidr(target).is_some());
+    let command = match explicit {
+        Some(Command::Ping) | None if cidr_target => Command::Sweep,
+        Some(Command::Ping) | None if args.contains(&"--path-monitor".to_string()) => Command::Trace,
+        Some(command) => command,
+        None => Command::Ping,
+    };
+    (command, args)
+}
+
+/// Parses the options every command shares, then runs `command` with them.
+fn run_command(command: Command, args: &[String]) {
     if args.len() < 2 {
-        println!("Usage: cargo run <target> [options]");
+        println!("Usage: cargo run [ping|sweep|trace] <target> [options]");
         println!("Example: cargo run 8.8.8.8 -c 5 -s 64 -w 1000 -ttl 128 -4");
         return;
     }
 
     let target = &args[1];
-    let count = get_argument(&args, "-c", 4);
-    let packet_size = get_argument(&args, "-s", 56) as usize;
-    let timeout = get_argument(&args, "-w", 1000);
-    let ttl = get_argument(&args, "-ttl", 128);
-    let continuous = args.contains(&"-t".to_string());
+    // `--once` is `-c 1 --compact` that also fails when the probe does, for
+    // shell conditionals like `ring host --once && echo up`.
+    let once = args.contains(&"--once".to_string());
+    let warmup = get_optional_argument(args, "--warmup-count").filter(|n| *n > 0).map(|n| {
+        let k = get_string_argument(args, "--anomaly-k").and_then(|value| value.parse::<f64>().ok());
+        (n as u32, k.unwrap_or(DEFAULT_ANOMALY_K).max(0.0))
+    });
+    if once && warmup.is_some() {
+        println!("--warmup-count keeps monitoring after the warmup, so it cannot be combined with --once");
+        return;
+    }
+    let path_monitor = command == Command::Trace;
+    // After a warmup, and when monitoring a path, the run carries on until
+    // stopped unless -c bounds it.
+    let default_count = if warmup.is_some() || path_monitor { None } else { Some(DEFAULT_COUNT) };
+    let count = match parse_count(args, once, default_count) {
+        Ok(count) => count,
+        Err(value) => {
+            println!("Invalid count: {} (expected a number of probes, or 0 or inf to run until stopped)", value);
+            return;
+        }
+    };
+    let packet_sizes = match get_string_argument(args, "-s") {
+        Some(value) => match parse_size_list(value) {
+            Some(sizes) => sizes,
+            None => {
+                println!("Invalid packet size list: {}", value);
+                return;
+            }
+        },
+        None => vec![56],
+    };
+    let packet_size = packet_sizes[0];
+    let timeout = get_argument(args, "-w", 1000);
+    let ttl = get_argument(args, "-ttl", 128);
+    let tos = match get_field_argument::<u8>(args, "-Q") {
+        Ok(tos) => tos,
+        Err(value) => {
+            println!("Invalid -Q: {} (expected a TOS byte, 0 to 255 or 0x00 to 0xff)", value);
+            return;
+        }
+    };
+    let tos_echo = args.contains(&"--tos-echo".to_string());
+    let broadcast = args.contains(&"-b".to_string()) || args.contains(&"--broadcast".to_string());
+    let discover_count = get_argument(args, "--discover-count", 1).clamp(1, u16::MAX as i32) as u16;
+    let verbose = args.contains(&"-v".to_string()) || args.contains(&"--verbose".to_string());
+    let randomize_id = args.contains(&"--randomize-id-per-packet".to_string());
+    let (identifier, start_seq) = match (get_field_argument::<u16>(args, "--id"), get_field_argument::<u16>(args, "--start-seq")) {
+        (Ok(identifier), Ok(start_seq)) => (identifier, start_seq),
+        (Err(value), _) => {
+            println!("Invalid --id: {} (expected 0 to 65535, or 0x0000 to 0xffff)", value);
+            return;
+        }
+        (_, Err(value)) => {
+            println!("Invalid --start-seq: {} (expected 0 to 65535, or 0x0000 to 0xffff)", value);
+            return;
+        }
+    };
+    if randomize_id && identifier.is_some() {
+        println!("--id and --randomize-id-per-packet both set the identifier; pick one");
+        return;
+    }
+    if let Some(start) = start_seq {
+        // `next_sequence` steps before it hands a number out.
+        GLOBAL_SEQUENCE.store(start.wrapping_sub(1), Ordering::Relaxed);
+    }
+    let graph = args.contains(&"--graph".to_string());
+    let live_stats = args.contains(&"--live-stats".to_string());
+    let max_rtt = get_optional_argument(args, "--max-rtt-ms").map(|ms| Duration::from_millis(ms.max(0) as u64));
+    let abort_on_max_rtt = args.contains(&"--abort-on-max-rtt".to_string());
+    let exit_reason = args.contains(&"--exit-reason".to_string());
+    let pause_on_error = get_optional_argument(args, "--pause-on-error").filter(|ms| *ms > 0).map(|ms| Duration::from_millis(ms as u64));
+    let grace = get_optional_argument(args, "--grace").filter(|ms| *ms > 0).map(|ms| Duration::from_millis(ms as u64));
+    let show_local_port = args.contains(&"--show-local-port".to_string());
+    let stop_if_window_loss = get_string_argument(args, "--stop-if-window-loss")
+        .and_then(|value| value.parse::<f64>().ok())
+        .map(|pct| (pct, get_argument(args, "--window-size", DEFAULT_LOSS_WINDOW as i32).max(1) as usize));
+    let mut interval = Duration::from_millis(get_argument(args, "-i", 1000).max(0) as u64);
+    if interval < MIN_UNPRIVILEGED_INTERVAL && !can_open_raw_socket() {
+        println!(
+            "Note: -i below {}ms needs raw socket privileges; using {}ms.",
+            MIN_UNPRIVILEGED_INTERVAL.as_millis(),
+            MIN_UNPRIVILEGED_INTERVAL.as_millis()
+        );
+        interval = MIN_UNPRIVILEGED_INTERVAL;
+    }
+    let loss_pacing = match get_string_argument(args, "--loss-pacing") {
+        Some("fixed") | None => LossPacing::Fixed,
+        Some("interval") => LossPacing::Interval,
+        Some(value) => {
+            println!("Invalid --loss-pacing: {} (expected fixed or interval)", value);
+            return;
+        }
+    };
+    let seed = get_string_argument(args, "--seed").and_then(|value| value.parse::<u64>().ok());
+    let retries = get_argument(args, "--retries", 0).max(0) as u32;
+    let compare = get_string_argument(args, "--compare");
+    let format = match get_string_argument(args, "--format").map(ReplyTemplate::parse) {
+        Some(Ok(template)) => Some(template),
+        Some(Err(e)) => {
+            println!("Invalid --format: {}", e);
+            return;
+        }
+        None => None,
+    };
+    let mark = match get_string_argument(args, "--mark") {
+        Some(value) => match value.parse::<u32>() {
+            Ok(mark) if cfg!(target_os = "linux") => Some(mark),
+            Ok(_) => {
+                println!("--mark sets SO_MARK, which is only available on Linux");
+                return;
+            }
+            Err(_) => {
+                println!("Invalid --mark: {}", value);
+                return;
+            }
+        },
+        None => None,
+    };
+    let summary_every = get_optional_argument(args, "--summary-every").filter(|n| *n > 0).map(|n| n as u32);
+    let max_samples = get_argument(args, "--max-samples", samples::DEFAULT_MAX_SAMPLES as i32).max(0) as usize;
+    // By default the largest reply to the largest `-s` fits, IP options included.
+    let largest_reply = packet_sizes.iter().max().copied().unwrap_or(0) + ICMP_HEADER_LEN + MAX_IPV4_HEADER_LEN;
+    let recv_buffer = get_argument(args, "--recv-buffer", largest_reply.max(DEFAULT_RECV_BUFFER) as i32).max(ICMP_HEADER_LEN as i32) as usize;
+    let trimmed_mean = match get_string_argument(args, "--trimmed-mean") {
+        Some(value) => match value.parse::<f64>() {
+            Ok(pct) if (0.0..50.0).contains(&pct) => Some(pct),
+            _ => {
+                println!("Invalid --trimmed-mean: expected a percentage from 0 up to 50");
+                return;
+            }
+        },
+        None => None,
+    };
+    let warn_on_ttl_below = get_optional_argument(args, "--warn-on-ttl-below").map(|n| n.clamp(0, 255) as u8);
+    let window = get_argument(args, "--window", 1).max(1) as usize;
+    let strict_reply_order = args.contains(&"--strict-reply-order".to_string());
+    let loose_match = args.contains(&"--loose-match".to_string());
+    let global_seq = args.contains(&"--global-seq".to_string());
+    if loose_match && window > 1 {
+        println!("--loose-match cannot be combined with --window: replies are told apart by sequence number");
+        return;
+    }
+    if stop_if_window_loss.is_some() && window > 1 {
+        println!("--stop-if-window-loss cannot be combined with --window yet");
+        return;
+    }
+    if warmup.is_some() && window > 1 {
+        println!("--warmup-count cannot be combined with --window yet");
+        return;
+    }
+    if pause_on_error.is_some() && window > 1 {
+        println!("--pause-on-error cannot be combined with --window yet");
+        return;
+    }
+    let payload_crc = args.contains(&"--payload-crc".to_string());
+    // A bare `--min-reply-size` expects the whole payload back.
+    let min_reply_size = args
+        .contains(&"--min-reply-size".to_string())
+        .then(|| get_optional_argument(args, "--min-reply-size").map_or(usize::MAX, |bytes| bytes.max(0) as usize));
+    let histogram = match get_string_argument(args, "--histogram-buckets") {
+        Some(value) => match histogram::parse_bounds(value) {
+            Some(bounds) => Some(bounds),
+            None => {
+                println!("Invalid --histogram-buckets: expected increasing millisecond bounds such as 1,2,5,10");
+                return;
+            }
+        },
+        None if args.contains(&"--histogram".to_string()) => Some(histogram::DEFAULT_BOUNDS.to_vec()),
+        None => None,
+    };
+    if payload_crc && packet_sizes.iter().any(|size| *size < packet::CRC_LEN) {
+        println!("--payload-crc needs at least {} bytes of data (-s)", packet::CRC_LEN);
+        return;
+    }
+    let zero_payload = args.contains(&"--zero-payload".to_string());
+    let show_delta = args.contains(&"--show-delta".to_string());
+    let trend_tolerance = get_string_argument(args, "--trend-tolerance-ms").map(|value| value.parse::<f64>());
+    let trend = match trend_tolerance {
+        Some(Ok(ms)) if ms >= 0.0 && ms.is_finite() => Some(Duration::from_secs_f64(ms / 1000.0)),
+        Some(_) => {
+            println!("Invalid --trend-tolerance-ms: expected a number of milliseconds");
+            return;
+        }
+        None if args.contains(&"--trend".to_string()) => Some(Duration::from_millis(DEFAULT_TREND_TOLERANCE_MS)),
+        None => None,
+    };
+    let dns_timeout = Duration::from_millis(get_argument(args, "--dns-timeout", 5000).max(1) as u64);
+    let mut resolvers = Vec::new();
+    for value in args.windows(2).filter(|pair| pair[0] == "--resolver").map(|pair| pair[1].as_str()) {
+        match dns::parse_resolver(value) {
+            Some(resolver) => resolvers.push(resolver),
+            None => {
+                println!("Invalid --resolver: {} (expected an IP address, optionally with a port)", value);
+                return;
+            }
+        }
+    }
+    dns::set_resolvers(resolvers);
+    let probe = match get_string_argument(args, "--probe") {
+        Some(value) => match Probe::parse(value) {
+            Some(probe) => probe,
+            None => {
+                println!("Invalid --probe: expected icmp, udp:<port> or tcp:<port>");
+                return;
+            }
+        },
+        None => Probe::Icmp,
+    };
+    let size_from_mtu = args.contains(&"--payload-size-from-mtu".to_string());
+    if size_from_mtu && (args.contains(&"-s".to_string()) || probe != Probe::Icmp) {
+        println!("--payload-size-from-mtu picks the ICMP echo payload size itself; drop -s and --probe");
+        return;
+    }
+    let repeat_runs = get_optional_argument(args, "--repeat-runs").filter(|runs| *runs > 1).map(|runs| runs as u32);
+    let run_gap = get_string_argument(args, "--run-gap").map(|value| value.parse::<f64>());
+    let run_gap = match run_gap {
+        Some(Ok(seconds)) if seconds >= 0.0 && seconds.is_finite() => Duration::from_secs_f64(seconds),
+        Some(_) => {
+            println!("Invalid --run-gap: expected a number of seconds");
+            return;
+        }
+        None => Duration::ZERO,
+    };
+    if repeat_runs.is_some() && count.is_none() {
+        println!("--repeat-runs repeats a count-based run; it cannot be combined with -t");
+        return;
+    }
+    if path_monitor && (probe != Probe::Icmp || once) {
+        println!("trace pings the hops with ICMP echo requests; drop --probe and --once");
+        return;
+    }
+    let retrace = match get_optional_argument(args, "--retrace") {
+        Some(seconds) if seconds <= 0 => None,
+        Some(seconds) => Some(Duration::from_secs(seconds as u64)),
+        None => Some(path::DEFAULT_RETRACE),
+    };
+    let detect_ratelimit = args.contains(&"--detect-ratelimit".to_string());
+    if detect_ratelimit && probe != Probe::Icmp {
+        println!("--detect-ratelimit re-probes with ICMP echo requests; drop --probe");
+        return;
+    }
+    let spoof_source = match get_string_argument(args, "--spoof-source") {
+        Some(value) => match value.parse::<Ipv4Addr>() {
+            Ok(_) if !args.contains(&"--i-know-what-im-doing".to_string()) => {
+                println!("--spoof-source sends packets that claim to come from another host; only use it on networks you");
+                println!("are authorised to test, and confirm with --i-know-what-im-doing.");
+                return;
+            }
+            Ok(_) if probe != Probe::Icmp || window > 1 || broadcast => {
+                println!("--spoof-source only works for plain ICMP echo runs; drop --probe, --window and -b");
+                return;
+            }
+            Ok(source) => Some(source),
+            Err(_) => {
+                println!("Invalid --spoof-source: {} (expected an IPv4 address)", value);
+                return;
+            }
+        },
+        None => None,
+    };
+    let bad_checksum = args.contains(&"--bad-checksum".to_string());
+    if bad_checksum && (probe != Probe::Icmp || window > 1 || broadcast) {
+        println!("--bad-checksum only works for plain ICMP echo runs; drop --probe, --window and -b");
+        return;
+    }
+    let address_index = match get_string_argument(args, "--address-index") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(index) => Some(index),
+            Err(_) => {
+                println!("Invalid --address-index: {}", value);
+                return;
+            }
+        },
+        None => None,
+    };
+    let interactive = args.contains(&"--interactive".to_string());
+    let compact = once || args.contains(&"--compact".to_string());
+    let summary_only_on_change = args.contains(&"--summary-only-on-change".to_string());
+    if summary_only_on_change || compact {
+        output::set_quiet(true);
+    }
+    let max_loss = get_string_argument(args, "--fail-if-loss-above").and_then(|value| value.parse::<f64>().ok());
+    let baseline_path = get_string_argument(args, "--baseline");
+    let save_baseline = get_string_argument(args, "--save-baseline");
+    let max_regression = get_string_argument(args, "--max-regression").and_then(|value| value.parse::<f64>().ok());
+    if (baseline_path.is_some() || save_baseline.is_some()) && packet_sizes.len() > 1 {
+        println!("--baseline and --save-baseline cover one run; pass a single -s size");
+        return;
+    }
+    let baseline = match baseline_path.map(|path| (path, baseline::Baseline::load(path))) {
+        Some((path, Ok(loaded))) => Some((path, loaded)),
+        Some((path, Err(e))) => {
+            println!("Failed to read baseline {}: {}", path, e);
+            return;
+        }
+        None => None,
+    };
+    let log_file = get_string_argument(args, "--log-file");
+    let log_level = get_string_argument(args, "--log-level");
 
-    println!("ringing {} with {} bytes of data:", target, packet_size);
+    if log_file.is_some() || log_level.is_some() {
+        let level = match log_level.map(logger::Level::parse) {
+            Some(Some(level)) => level,
+            Some(None) => {
+                println!("Invalid log level: expected error, warn, info, debug or trace");
+                return;
+            }
+            None => logger::Level::Info,
+        };
 
-    let target_ip = match target.parse::<IpAddr>() {
-        Ok(ip) => ip,
-        Err(_) => match resolve_target(target) {
-            Ok(ip) => ip,
+        if let Err(e) = logger::init(log_file, level) {
+            println!("Failed to open log file: {}", e);
+            return;
+        }
+    }
+
+    // Structured output replaces the text lines rather than mixing with them.
+    let json_pretty = args.contains(&"--json-pretty".to_string());
+    if json_pretty || args.contains(&"--json".to_string()) {
+        ndjson::to_stdout(if json_pretty { ndjson::Style::Pretty } else { ndjson::Style::Line });
+        output::set_quiet(true);
+    }
+
+    if let Some(path) = get_string_argument(args, "--ndjson-out") {
+        let rotate_size = get_string_argument(args, "--rotate-size").and_then(|value| value.parse::<u64>().ok());
+        if let Err(e) = ndjson::open(path, rotate_size) {
+            println!("Failed to open NDJSON output {}: {}", path, e);
+            return;
+        }
+    }
+
+    if let Some(value) = get_string_argument(args, "--id-endian") {
+        match packet::Endian::parse(value) {
+            Some(endian) => packet::set_field_endian(endian),
+            None => {
+                println!("Invalid --id-endian: {} (expected big or little)", value);
+                return;
+            }
+        }
+    }
+    if let Some(value) = get_string_argument(args, "--unit") {
+        match output::Unit::parse(value) {
+            Some(unit) => output::set_unit(unit),
+            None => {
+                println!("Invalid --unit: {} (expected ms, us or s)", value);
+                return;
+            }
+        }
+    }
+
+    if let Some(tag) = get_string_argument(args, "--tag") {
+        json::set_tag(tag);
+    }
+
+    if let Some(path) = get_string_argument(args, "--pcap-out") {
+        if let Err(e) = pcap::open(path) {
+            println!("Failed to open packet capture {}: {}", path, e);
+            return;
+        }
+    }
+
+    if let Some(url) = get_string_argument(args, "--webhook") {
+        if let Err(e) = webhook::configure(url) {
+            println!("Invalid --webhook: {}", e);
+            return;
+        }
+    }
+
+    if args.contains(&"--show-asn".to_string()) {
+        let Some(path) = get_string_argument(args, "--asn-db") else {
+            println!("--show-asn needs an IP-to-ASN table: --asn-db <path>");
+            return;
+        };
+        match asn::load(path) {
+            Ok(loaded) => log_info!("loaded {} ASN ranges from {}", loaded, path),
             Err(e) => {
-                println!("Invalid target address: {}", e);
+                println!("Failed to read ASN database {}: {}", path, e);
                 return;
             }
-        },
+        }
+    }
+
+    if let Some(path) = get_string_argument(args, "--report-socket") {
+        if let Err(e) = report::connect(path) {
+            log_error!("failed to connect to report socket {}: {}", path, e);
+            println!("Warning: cannot reach report socket {} ({}); continuing without it.", path, e);
+        }
+    }
+
+    if args.contains(&"--syslog".to_string()) {
+        let facility = get_string_argument(args, "--syslog-facility").unwrap_or("user");
+        let tag = get_string_argument(args, "--syslog-tag").unwrap_or("ring");
+        match syslog::open(facility, tag) {
+            Ok(()) => log_info!("logging events to syslog as {} ({})", tag, facility),
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => println!("Note: {}; ignoring --syslog.", e),
+            Err(e) => {
+                println!("Invalid --syslog settings: {}", e);
+                return;
+            }
+        }
+    }
+
+    let options = Options {
+        count,
+        packet_size,
+        timeout,
+        ttl,
+        tos,
+        tos_echo,
+        broadcast,
+        verbose,
+        randomize_id,
+        identifier,
+        start_seq,
+        graph,
+        live_stats,
+        retries,
+        interval,
+        loss_pacing,
+        seed,
+        max_rtt,
+        abort_on_max_rtt,
+        exit_reason,
+        grace,
+        pause_on_error,
+        spoof_source,
+        bad_checksum,
+        stop_if_window_loss,
+        warmup,
+        show_local_port,
+        format,
+        mark,
+        interactive,
+        summary_every,
+        max_samples,
+        trimmed_mean,
+        recv_buffer,
+        warn_on_ttl_below,
+        window,
+        strict_reply_order,
+        loose_match,
+        global_seq,
+        payload_crc,
+        min_reply_size,
+        histogram,
+        zero_payload,
+        show_delta,
+        trend,
+        discover_count,
     };
 
-    run_ring(target_ip, count, packet_size, timeout, ttl, continuous);
-}
+    // Needs no target: `ring --self-test` probes loopback on its own.
+    if args.contains(&"--self-test".to_string()) {
+        std::process::exit(selftest::run_self_test(&options));
+    }
 
-fn get_argument(args: &[String], option: &str, default: i32) -> i32 {
-    if let Some(index) = args.iter().position(|arg| arg == option) {
-        if let Some(value) = args.get(index + 1) {
-            if let Ok(num) = value.parse::<i32>() {
-                return num;
+    // `cat hosts.txt | ring --stdin` takes its targets from the pipe instead.
+    if args.contains(&"--stdin".to_string()) || args.contains(&"--targets-from-stdin".to_string()) {
+        let all_up = run_stdin_targets(probe, &options, dns_timeout, address_index);
+        std::process::exit(if all_up { 0 } else { 1 });
+    }
+
+    // Scripts get the address ring would ping without a probe going out, so
+    // only the address is printed.
+    if args.contains(&"--print-resolved-and-exit".to_string()) {
+        output::set_quiet(true);
+        match lookup_target(target, dns_timeout, address_index) {
+            Ok(ip) => println!("{}", ip),
+            Err(e) => {
+                report_error(json::error("resolve", target, &e), format!("Cannot resolve {}: {}", target, e));
+                std::process::exit(1);
             }
         }
+        return;
     }
-    default
-}
 
-fn run_ring(target: IpAddr, mut count: i32, packet_size: usize, timeout: i32, ttl: i32, continuous: bool) {
-    let packet = create_icmp_packet(packet_size, target);
-    let socket = create_socket(target, ttl, timeout).expect("Failed to create socket");
+    if command == Command::Sweep {
+        sweep_command(target, args, &options);
+        return;
+    }
 
-    let dest_addr = match target {
-        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V4(ip), 0),
-        IpAddr::V6(ip) => SocketAddr::new(IpAddr::V6(ip), 0),
+    if args.contains(&"--router-solicit".to_string()) {
+        say!("soliciting IPv6 routers on {}:", target);
+        router::run_router_solicitation(target, &options);
+        return;
+    }
+
+    let target_ip = match lookup_target(target, dns_timeout, address_index) {
+        Ok(ip) => ip,
+        Err(e) if compact => {
+            log_error!("cannot resolve {}: {}", target, e);
+            report_error(json::error("resolve", target, &e), format!("{} DOWN", target));
+            if once {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Err(e) => {
+            report_error(json::error("resolve", target, &e), format!("Invalid target address: {}", e));
+            return;
+        }
+    };
+    if options.verbose && target_ip.is_loopback() {
+        say!("Note: {} is a loopback address; replies come from this host.", target_ip);
+    }
+
+    let (options, packet_sizes) = if size_from_mtu {
+        let Some(mtu) = route_mtu(target_ip) else {
+            report_error(
+                json::error("mtu", target_ip, "cannot query the route MTU"),
+                format!("Cannot query the MTU of the route to {}; pass -s instead of --payload-size-from-mtu.", target_ip),
+            );
+            return;
+        };
+        let size = max_payload(target_ip, mtu) as usize;
+        let ip_header = if target_ip.is_ipv4() { 20 } else { 40 };
+        say!(
+            "Payload size from the {}-byte MTU towards {}: -s {} ({} IP and {} ICMP header bytes)",
+            mtu, target_ip, size, ip_header, ICMP_HEADER_LEN
+        );
+        let recv_buffer = options.recv_buffer.max(size + ICMP_HEADER_LEN + MAX_IPV4_HEADER_LEN);
+        (Options { packet_size: size, recv_buffer, ..options }, vec![size])
+    } else {
+        (options, packet_sizes)
     };
 
-    let mut sent = 0;
-    let mut received = 0;
-    let mut min_rtt = Duration::MAX;
-    let mut max_rtt = Duration::ZERO;
-    let mut total_rtt = Duration::ZERO;
+    if let Some(other) = compare {
+        say!("ringing {} with {} bytes of data:", target, options.packet_size);
+        match lookup_target(other, dns_timeout, None) {
+            Ok(other_ip) => run_compare([target_ip, other_ip], &options),
+            Err(e) => report_error(json::error("resolve", other, &e), format!("Invalid comparison address: {}", e)),
+        }
+        return;
+    }
+
+    if let Some(interface) = get_string_argument(args, "--neighbor-solicit") {
+        let IpAddr::V6(target_ip) = target_ip else {
+            println!("--neighbor-solicit needs an IPv6 target; use ARP for IPv4 neighbors.");
+            return;
+        };
+        say!("soliciting neighbor {} on {}:", target_ip, interface);
+        neighbor::run_neighbor_solicitation(target_ip, interface, &options);
+        return;
+    }
 
-    while continuous || count > 0 {
-        let result = send_and_receive_ring(&socket, &packet, &dest_addr, timeout);
+    if command == Command::Trace {
+        say!("tracing the path to {}, then pinging every hop along it:", target);
+        path::run_path_monitor(target_ip, &options, retrace);
+        return;
+    }
 
-        if let Ok(rtt) = result {
-            received += 1;
-            total_rtt += rtt;
-            min_rtt = min_rtt.min(rtt);
-            max_rtt = max_rtt.max(rtt);
+    if args.contains(&"--asymmetry".to_string()) {
+        say!("estimating path asymmetry to {} with TTL-limited echo requests:", target);
+        asymmetry::run_asymmetry(target_ip, &options);
+        return;
+    }
 
-            println!(
-                "Reply from {}: bytes={} time={}ms TTL={}",
-                target,
-                packet_size,
-                rtt.as_millis(),
-                ttl
-            );
-        } else {
-            println!("Request timed out.");
+    if args.contains(&"--address-mask".to_string()) {
+        say!("ringing {} with ICMP address mask requests:", target);
+        run_address_mask(target_ip, &options);
+        return;
+    }
+
+    // `--dry-run` has nothing to show but the explanation, so it implies `--explain`.
+    let dry_run = args.contains(&"--dry-run".to_string());
+    if dry_run || args.contains(&"--explain".to_string()) {
+        match probe {
+            Probe::Icmp => {
+                for size in &packet_sizes {
+                    explain::explain(target_ip, &Options { packet_size: *size, ..options.clone() });
+                }
+            }
+            _ => println!("--explain only describes ICMP echo requests."),
+        }
+        if dry_run {
+            return;
+        }
+        println!();
+    }
+
+    if probe == Probe::Icmp {
+        warn_if_fragmented(target_ip, &packet_sizes);
+    }
+
+    let mut results = Vec::with_capacity(packet_sizes.len());
+    for (index, size) in packet_sizes.iter().enumerate() {
+        if index > 0 {
+            say!();
+        }
+        match probe {
+            Probe::Tcp(port) => say!("ringing {} on TCP port {}:", target, port),
+            Probe::Udp(port) => say!("ringing {} on UDP port {} with {} bytes of data:", target, port, size),
+            Probe::Icmp => say!("ringing {} with {} bytes of data:", target, size),
         }
 
-        sent += 1;
-        if !continuous {
-            count -= 1;
+        let options = Options { packet_size: *size, ..options.clone() };
+        let stats = match repeat_runs {
+            Some(runs) => repeat::run_repeated(target_ip, probe, &options, runs, run_gap),
+            None => run_probes(target_ip, probe, &options),
+        };
+        let aborted = (options.abort_on_max_rtt && stats.rtt_violations > 0) || stats.exit_reason != ExitReason::CountReached;
+        results.push((*size, stats));
+        if aborted {
+            break;
         }
+    }
+
+    if results.len() > 1 {
+        print_size_matrix(target_ip, &results);
+    }
 
-        if count > 0 || continuous {
-            sleep(Duration::from_secs(1));
+    if detect_ratelimit {
+        for (size, stats) in &results {
+            throttle::detect(target_ip, &Options { packet_size: *size, ..options.clone() }, stats);
         }
     }
 
-    println!("\nring statistics for {}:", target);
-    println!(
-        "    Packets: Sent = {}, Received = {}, Lost = {} ({:.0}% loss),",
-        sent,
-        received,
-        sent - received,
-        if sent > 0 {
-            100.0 * (sent - received) as f32 / sent as f32
-        } else {
-            0.0
+    let mut regression = None;
+    if let Some((size, stats)) = results.first() {
+        let current = baseline::Baseline::from_stats(target_ip, *size, stats);
+        if let Some((path, baseline)) = &baseline {
+            regression = baseline::compare(&current, baseline, path, max_regression);
         }
-    );
+        if let Some(path) = save_baseline {
+            match current.save(path) {
+                Ok(()) => say!("Saved baseline to {}", path),
+                Err(e) => report_error(json::error("baseline", path, &e), format!("Failed to save baseline {}: {}", path, e)),
+            }
+        }
+    }
+
+    if compact {
+        for (size, stats) in &results {
+            match results.len() {
+                1 => println!("{}", compact_summary(target, stats)),
+                _ => println!("{} size={}", compact_summary(target, stats), size),
+            }
+        }
+    }
+
+    // Cron-friendly mode: stay silent on a clean run, otherwise show the
+    // summaries that were held back and fail.
+    let mut unhealthy = false;
+    if summary_only_on_change {
+        unhealthy = results.iter().any(|(_, stats)| stats.lost() > 0 || stats.rtt_violations > 0);
+        if !unhealthy {
+            return;
+        }
+
+        output::set_quiet(false);
+        for (_, stats) in &results {
+            print_statistics(target_ip, stats);
+            print_histogram(stats, &options);
+        }
+        if results.len() > 1 {
+            print_size_matrix(target_ip, &results);
+        }
+    }
+
+    if results.iter().any(|(_, stats)| stats.exit_reason == ExitReason::PermissionDenied) {
+        std::process::exit(EXIT_PERMISSION_DENIED);
+    }
+
+    if options.abort_on_max_rtt && results.iter().any(|(_, stats)| stats.rtt_violations > 0) {
+        std::process::exit(1);
+    }
+
+    if results.iter().any(|(_, stats)| stats.exit_reason == ExitReason::WindowLoss) {
+        std::process::exit(1);
+    }
+
+    if let Some(max_loss) = max_loss {
+        if let Some((_, stats)) = results.iter().find(|(_, stats)| stats.loss_percent() > max_loss) {
+            let message = format!("Loss {:.1}% exceeds the allowed {}%", stats.loss_percent(), max_loss);
+            report_error(json::error("loss_limit", target_ip, &message), message);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(regression) = regression {
+        report_error(json::error("regression", target_ip, &regression), format!("Regression against the baseline: {}", regression));
+        std::process::exit(1);
+    }
+
+    if unhealthy || (once && results.iter().any(|(_, stats)| stats.received == 0)) {
+        std::process::exit(1);
+    }
+}
+
+/// Pings every host in the CIDR block `target` (`ring sweep`).
+fn sweep_command(target: &str, args: &[String], options: &Options) {
+    let Some((network, prefix)) = sweep::parse_cidr(target) else {
+        println!("sweep needs a CIDR block such as 10.0.0.0/24, not {}", target);
+        return;
+    };
+    let parallel = get_argument(args, "--parallel", 1).max(1) as usize;
+    let max_hosts = get_argument(args, "--max-hosts", sweep::DEFAULT_MAX_HOSTS as i32).max(0) as u128;
+    let hosts = sweep::host_count(network, prefix);
+    let mut rates = [None, None];
+    for (rate, flag) in rates.iter_mut().zip(["--max-pps", "--per-host-rate"]) {
+        if let Some(value) = get_string_argument(args, flag) {
+            match value.parse::<f64>() {
+                Ok(parsed) if parsed > 0.0 && parsed.is_finite() => *rate = Some(parsed),
+                _ => {
+                    println!("Invalid {}: expected a positive number of probes per second", flag);
+                    return;
+                }
+            }
+        }
+    }
+    let pacing = sweep::Pacing { max_pps: rates[0], per_host_rate: rates[1] };
 
-    if received > 0 {
-        println!("Approximate round trip times in milli-seconds:");
+    if hosts > sweep::ABSOLUTE_MAX_HOSTS {
+        println!("Refusing to sweep {} hosts in {}; the limit is {}.", hosts, target, sweep::ABSOLUTE_MAX_HOSTS);
+        return;
+    }
+    if hosts > max_hosts && !args.contains(&"--force".to_string()) {
         println!(
-            "    Minimum = {}ms, Maximum = {}ms, Average = {}ms",
-            min_rtt.as_millis(),
-            max_rtt.as_millis(),
-            total_rtt.as_millis() / received as u128
+            "Refusing to sweep {} hosts in {} (limit {}); pass --force or raise --max-hosts.",
+            hosts, target, max_hosts
         );
+        return;
+    }
+
+    say!("ringing {} hosts in {} with {} bytes of data:", hosts, target, options.packet_size);
+    sweep::run_sweep(target, &sweep::expand_cidr(network, prefix), options, parallel, &pacing);
+}
+
+/// Runs the probes `--probe` and `--window` ask for against one target.
+fn run_probes(target: IpAddr, probe: Probe, options: &Options) -> PingStatistics {
+    if probe != Probe::Icmp {
+        transport::run_transport(target, probe, options)
+    } else if options.window > 1 {
+        window::run_windowed(target, options, options.window)
+    } else {
+        run_ring(target, options)
     }
 }
 
-fn create_socket(target: IpAddr, ttl: i32, timeout: i32) -> io::Result<Socket> {
+/// Pings each target read from stdin, one per line, as soon as its line
+/// arrives, and prints a `--compact` line for it. Blank lines and lines
+/// starting with `#` are skipped. Returns whether every target answered.
+fn run_stdin_targets(probe: Probe, options: &Options, dns_timeout: Duration, address_index: Option<usize>) -> bool {
+    output::set_quiet(true);
+    let mut all_up = true;
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                log_error!("failed to read targets from stdin: {}", e);
+                println!("Failed to read targets from stdin: {}", e);
+                return false;
+            }
+        };
+        let target = line.trim();
+        if target.is_empty() || target.starts_with('#') {
+            continue;
+        }
+
+        let target_ip = match lookup_target(target, dns_timeout, address_index) {
+            Ok(ip) => ip,
+            Err(e) => {
+                report_error(json::error("resolve", target, &e), format!("{} DOWN", target));
+                all_up = false;
+                continue;
+            }
+        };
+        let stats = run_probes(target_ip, probe, options);
+        all_up &= stats.received > 0;
+        if !ndjson::on_stdout() {
+            println!("{}", compact_summary(target, &stats));
+        }
+    }
+    all_up
+}
+
+/// Reads the probe count from `-c` (or `--count`), where `0` and `inf` mean
+/// no limit, as does `-t`, and `default` applies without either. `--once`
+/// always sends one. Returns the offending value when it is not a count.
+fn parse_count(args: &[String], once: bool, default: Option<u32>) -> Result<Option<u32>, &str> {
+    if once {
+        return Ok(Some(1));
+    }
+    if args.contains(&"-t".to_string()) {
+        return Ok(None);
+    }
+    match get_string_argument(args, "-c").or_else(|| get_string_argument(args, "--count")) {
+        None => Ok(default),
+        Some(value) if value.eq_ignore_ascii_case("inf") => Ok(None),
+        Some(value) => match value.parse::<u32>() {
+            Ok(0) => Ok(None),
+            Ok(count) => Ok(Some(count)),
+            Err(_) => Err(value),
+        },
+    }
+}
+
+/// Counts one probe off a `-c` budget; an unlimited run never runs out.
+fn count_down(remaining: &mut Option<u32>) {
+    if let Some(left) = remaining {
+        *left = left.saturating_sub(1);
+    }
+}
+
+fn describe_count(count: Option<u32>) -> String {
+    count.map_or("unlimited".to_string(), |count| count.to_string())
+}
+
+/// Parses `-s` values such as `64` or `64,256,1024`.
+fn parse_size_list(value: &str) -> Option<Vec<usize>> {
+    value.split(',').map(|size| size.trim().parse::<usize>().ok()).collect()
+}
+
+fn print_size_matrix(target: IpAddr, results: &[(usize, PingStatistics)]) {
+    say!("\nring size matrix for {}:", target);
+    say!("    {:>6}  {:>6}  {:>8}  {:>6}  {:>8}  {:>8}  {:>8}", "Size", "Sent", "Received", "Loss", "Min", "Avg", "Max");
+
+    for (size, stats) in results {
+        let (min, avg, max) = match (stats.average_rtt(), stats.rtt_range()) {
+            (Some(average), Some((min, max))) => (output::rtt(min), output::rtt(average), output::rtt(max)),
+            _ => ("-".to_string(), "-".to_string(), "-".to_string()),
+        };
+
+        say!(
+            "    {:>6}  {:>6}  {:>8}  {:>5.0}%  {:>8}  {:>8}  {:>8}",
+            size,
+            stats.sent,
+            stats.received,
+            stats.loss_percent(),
+            min,
+            avg,
+            max
+        );
+    }
+}
+
+/// Parses or resolves `target`. `address_index` picks one of several
+/// resolved addresses instead of the default.
+fn lookup_target(target: &str, dns_timeout: Duration, address_index: Option<usize>) -> Result<IpAddr, String> {
+    let ip = match target.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => match resolve_target(target, dns_timeout, address_index) {
+            Ok(ip) => {
+                log_info!("resolved {} to {}", target, ip);
+                ip
+            }
+            Err(e) => {
+                log_error!("resolution of {} failed: {}", target, e);
+                return Err(e);
+            }
+        },
+    };
+
+    // `::ffff:a.b.c.d` only stands for an IPv4 host: ICMPv6 sent to it never
+    // reaches anything, so use the IPv4 address it wraps.
+    let ip = match ip {
+        IpAddr::V6(mapped) => match mapped.to_ipv4_mapped() {
+            Some(ipv4) => {
+                log_info!("unwrapped IPv4-mapped address {} to {}", mapped, ipv4);
+                say!("Note: {} is an IPv4-mapped IPv6 address; pinging {} over ICMPv4.", mapped, ipv4);
+                IpAddr::V4(ipv4)
+            }
+            None => ip,
+        },
+        IpAddr::V4(_) => ip,
+    };
+
+    // The kernel's handling of an unspecified destination differs between
+    // platforms and surfaces as a confusing send error, so reject it here.
+    if ip.is_unspecified() {
+        return Err(format!("{} is the unspecified address and cannot be pinged", ip));
+    }
+    Ok(ip)
+}
+
+#[derive(Clone)]
+struct Options {
+    /// Probes to send; `None` runs until interrupted (`-t`, `-c 0`, `-c inf`).
+    count: Option<u32>,
+    packet_size: usize,
+    timeout: i32,
+    ttl: i32,
+    tos: Option<u8>,
+    tos_echo: bool,
+    broadcast: bool,
+    verbose: bool,
+    randomize_id: bool,
+    /// Echo identifier from `--id`, in place of the process ID.
+    identifier: Option<u16>,
+    /// First sequence number (`--start-seq`); numbering starts at 1 otherwise.
+    start_seq: Option<u16>,
+    graph: bool,
+    live_stats: bool,
+    retries: u32,
+    interval: Duration,
+    loss_pacing: LossPacing,
+    seed: Option<u64>,
+    max_rtt: Option<Duration>,
+    abort_on_max_rtt: bool,
+    /// Print why the run ended (`--exit-reason`); Ctrl-C then ends it cleanly.
+    exit_reason: bool,
+    /// How long to keep listening for replies to lost probes once the run is done.
+    grace: Option<Duration>,
+    /// First pause once socket errors persist (`--pause-on-error`).
+    pause_on_error: Option<Duration>,
+    /// Source address written into hand-built IPv4 headers (`--spoof-source`).
+    spoof_source: Option<Ipv4Addr>,
+    /// Send echo requests with a deliberately wrong checksum (`--bad-checksum`).
+    bad_checksum: bool,
+    /// Loss percentage and probe count for `--stop-if-window-loss`.
+    stop_if_window_loss: Option<(f64, usize)>,
+    /// Probes in the `--warmup-count` baseline and the `--anomaly-k` factor.
+    warmup: Option<(u32, f64)>,
+    show_local_port: bool,
+    format: Option<ReplyTemplate>,
+    mark: Option<u32>,
+    interactive: bool,
+    summary_every: Option<u32>,
+    max_samples: usize,
+    /// Percent trimmed from each end for `--trimmed-mean`.
+    trimmed_mean: Option<f64>,
+    /// Bytes read per datagram (`--recv-buffer`); anything beyond is cut off.
+    recv_buffer: usize,
+    warn_on_ttl_below: Option<u8>,
+    window: usize,
+    strict_reply_order: bool,
+    /// Match replies on identifier alone (`--loose-match`).
+    loose_match: bool,
+    global_seq: bool,
+    payload_crc: bool,
+    /// Payload bytes a reply has to echo, capped at the size sent (`--min-reply-size`).
+    min_reply_size: Option<usize>,
+    /// Bucket bounds in milliseconds when `--histogram` is on.
+    histogram: Option<Vec<f64>>,
+    zero_payload: bool,
+    show_delta: bool,
+    /// How far an RTT may move and still count as steady when `--trend` is on.
+    trend: Option<Duration>,
+    /// Echo requests sent per responder collection (`--discover-count`).
+    discover_count: u16,
+}
+
+/// Whether a probe that times out still fits in one `-i` slot (`--loss-pacing`).
+#[derive(Clone, Copy, PartialEq)]
+enum LossPacing {
+    /// The next probe is due one interval after the lost one was sent.
+    Fixed,
+    /// The next probe is due one full interval after the timeout expired.
+    Interval,
+}
+
+/// Why a run stopped sending probes.
+#[derive(Clone, Copy, PartialEq)]
+enum ExitReason {
+    /// Every probe `-c` asked for was sent.
+    CountReached,
+    /// Ctrl-C, caught under `--exit-reason`.
+    Interrupted,
+    /// `q` in `--interactive` mode.
+    Quit,
+    /// A reply exceeded `--max-rtt-ms` under `--abort-on-max-rtt`.
+    RttLimit,
+    /// Loss over the last `--window-size` probes passed `--stop-if-window-loss`.
+    WindowLoss,
+    /// The socket started refusing with `EACCES`/`EPERM`.
+    PermissionDenied,
+}
+
+impl ExitReason {
+    fn describe(self) -> &'static str {
+        match self {
+            ExitReason::CountReached => "count reached",
+            ExitReason::Interrupted => "interrupted (SIGINT)",
+            ExitReason::Quit => "quit from the console",
+            ExitReason::RttLimit => "RTT limit exceeded",
+            ExitReason::WindowLoss => "window loss limit",
+            ExitReason::PermissionDenied => "permission denied",
+        }
+    }
+
+    /// Whether `--grace` still applies: not when the user or the kernel cut
+    /// the run short.
+    fn waits_for_stragglers(self) -> bool {
+        !matches!(self, ExitReason::Interrupted | ExitReason::Quit | ExitReason::PermissionDenied)
+    }
+
+    /// The value of `exit_reason` in JSON summaries.
+    fn key(self) -> &'static str {
+        match self {
+            ExitReason::CountReached => "count",
+            ExitReason::Interrupted => "interrupted",
+            ExitReason::Quit => "quit",
+            ExitReason::RttLimit => "max_rtt",
+            ExitReason::WindowLoss => "window_loss",
+            ExitReason::PermissionDenied => "permission_denied",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SocketKind {
+    Raw,
+    Dgram,
+}
+
+impl SocketKind {
+    fn name(self) -> &'static str {
+        match self {
+            SocketKind::Raw => "SOCK_RAW",
+            SocketKind::Dgram => "SOCK_DGRAM",
+        }
+    }
+}
+
+struct Reply {
+    sequence: u16,
+    source: IpAddr,
+    bytes: usize,
+    rtt: Duration,
+    tos: Option<u8>,
+    /// TTL / hop limit the reply arrived with, when the platform reports it.
+    ttl: Option<u8>,
+    /// Result of the `--payload-crc` check; `None` when it was not made.
+    payload_crc: Option<bool>,
+    /// Packets the kernel had dropped from this socket's receive queue by the
+    /// time the reply was read (Linux `SO_RXQ_OVFL`).
+    rx_drops: Option<u32>,
+    /// The datagram did not fit the receive buffer and was cut short.
+    truncated: bool,
+    /// The part of `rtt` spent between the kernel receiving the reply and
+    /// ring reading it, when kernel timestamps are available.
+    delivery: Option<Duration>,
+    /// How long the send call for the request took, which `rtt` includes.
+    send: Option<Duration>,
+}
+
+impl Reply {
+    /// The RTT without the local overhead on either end: the send call and
+    /// the delay between the kernel receiving the reply and ring reading it.
+    fn network_rtt(&self) -> Option<Duration> {
+        let delivery = self.delivery?;
+        Some(self.rtt.saturating_sub(delivery + self.send.unwrap_or_default()))
+    }
+}
+
+/// Type, code, checksum, identifier and sequence number.
+const ICMP_HEADER_LEN: usize = 8;
+
+/// An IPv4 header with the full 40 bytes of options.
+const MAX_IPV4_HEADER_LEN: usize = 60;
+
+/// An IPv4 header without options, as `--spoof-source` builds it.
+const IPV4_HEADER_LEN: usize = 20;
+const ICMPV4_PROTOCOL: u8 = 1;
+
+/// TTLs operating systems start their packets with. A reply carrying one of
+/// these unchanged is normal from a host next door, so matching an outgoing
+/// TTL of the same value says nothing about reflection.
+const COMMON_INITIAL_TTLS: [i32; 4] = [32, 64, 128, 255];
+
+/// Intervals below this count as a fast run, whose summary shows the rate
+/// actually achieved.
+const FAST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The `--trend` band within which an RTT counts as unchanged.
+const DEFAULT_TREND_TOLERANCE_MS: u64 = 1;
+
+/// Socket errors in a row after which `--pause-on-error` starts pausing.
+const ERROR_STREAK: u32 = 3;
+
+/// Longest pause `--pause-on-error` backs off to.
+const MAX_ERROR_PAUSE: Duration = Duration::from_secs(60);
+
+/// Lost probes whose late replies `--grace` can still match.
+const GRACE_TRACKED: usize = 256;
+
+/// How often a sleep between probes checks for Ctrl-C under `--exit-reason`.
+const INTERRUPT_POLL: Duration = Duration::from_millis(50);
+
+/// Probes sent without `-c`.
+const DEFAULT_COUNT: u32 = 4;
+
+/// Multiple of the warmup mdev a reply may exceed the warmup average by
+/// without `--anomaly-k`.
+const DEFAULT_ANOMALY_K: f64 = 3.0;
+
+/// Probes `--stop-if-window-loss` looks back over without `--window-size`.
+const DEFAULT_LOSS_WINDOW: usize = 20;
+
+/// Smallest default for `--recv-buffer`.
+const DEFAULT_RECV_BUFFER: usize = 1024;
+
+/// How many identifiers from earlier probes are remembered so their late
+/// replies can be told apart from other programs' echo traffic.
+const RECENT_ID_LIMIT: usize = 64;
+
+/// Number of recent probes shown by `--graph`.
+const GRAPH_WIDTH: usize = 40;
+
+struct PingStatistics {
+    sent: u32,
+    received: u32,
+    min_rtt: Duration,
+    max_rtt: Duration,
+    total_rtt: Duration,
+    /// Probes that timed out at least once and were retransmitted.
+    retried: u32,
+    /// Retried probes that eventually got a reply.
+    recovered: u32,
+    /// Replies slower than `--max-rtt-ms`.
+    rtt_violations: u32,
+    /// Replies below `--warn-on-ttl-below`.
+    ttl_warnings: u32,
+    /// The outgoing TTL, when it is unusual enough that replies carrying it
+    /// back point at a responder reflecting it rather than setting its own.
+    reflectable_ttl: Option<u8>,
+    /// Replies that carried a TTL, and those whose TTL was `reflectable_ttl`.
+    ttl_replies: u32,
+    reflected_ttls: u32,
+    /// Replies whose source address was not the target.
+    other_responders: u32,
+    /// ICMP errors received instead of replies, by kind.
+    icmp_errors: Vec<(IcmpError, u32)>,
+    /// Highest receive-queue drop count the kernel reported for the socket.
+    rx_drops: u32,
+    /// Replies that arrived ahead of an earlier, still-unanswered probe.
+    reordered: u32,
+    /// Late replies to probes `--strict-reply-order` had already counted as lost.
+    duplicates: u32,
+    /// Replies whose payload failed the `--payload-crc` check.
+    crc_mismatches: u32,
+    /// Smallest next-hop MTU reported by a too-big error.
+    path_mtu: Option<u32>,
+    /// Replies longer than `--recv-buffer`.
+    truncated: u32,
+    /// Replies that echoed less payload than `--min-reply-size` asks for.
+    short_replies: u32,
+    /// Replies to probes counted as lost that turned up during `--grace`.
+    late_replies: u32,
+    /// RTT above which replies after the `--warmup-count` probes are anomalies.
+    anomaly_threshold: Option<Duration>,
+    /// Replies slower than `anomaly_threshold`.
+    anomalies: u32,
+    /// Sum and count of the kernel-to-ring delivery delays replies reported.
+    total_delivery: Duration,
+    deliveries: u32,
+    /// Sum, count and maximum of the send-call times of answered probes.
+    total_send: Duration,
+    sends: u32,
+    max_send: Duration,
+    /// Whether the summary breaks the RTT into send, network and delivery time (`-v`).
+    show_receive_path: bool,
+    /// Wall-clock time from the first probe to the last reply or timeout.
+    elapsed: Duration,
+    /// Whether the summary shows the achieved send and receive rates, which
+    /// only say something in the faster modes.
+    show_rate: bool,
+    exit_reason: ExitReason,
+    samples: RttSamples,
+    /// Percent of samples dropped from each end for `--trimmed-mean`.
+    trim_percent: Option<f64>,
+}
+
+impl PingStatistics {
+    fn new() -> PingStatistics {
+        PingStatistics::with_sample_limit(samples::DEFAULT_MAX_SAMPLES)
+    }
+
+    fn with_sample_limit(limit: usize) -> PingStatistics {
+        PingStatistics {
+            sent: 0,
+            received: 0,
+            min_rtt: Duration::MAX,
+            max_rtt: Duration::ZERO,
+            total_rtt: Duration::ZERO,
+            retried: 0,
+            recovered: 0,
+            rtt_violations: 0,
+            ttl_warnings: 0,
+            reflectable_ttl: None,
+            ttl_replies: 0,
+            reflected_ttls: 0,
+            other_responders: 0,
+            icmp_errors: Vec::new(),
+            rx_drops: 0,
+            reordered: 0,
+            duplicates: 0,
+            crc_mismatches: 0,
+            path_mtu: None,
+            truncated: 0,
+            short_replies: 0,
+            late_replies: 0,
+            anomaly_threshold: None,
+            anomalies: 0,
+            total_delivery: Duration::ZERO,
+            deliveries: 0,
+            total_send: Duration::ZERO,
+            sends: 0,
+            max_send: Duration::ZERO,
+            show_receive_path: false,
+            elapsed: Duration::ZERO,
+            show_rate: false,
+            exit_reason: ExitReason::CountReached,
+            samples: RttSamples::new(limit),
+            trim_percent: None,
+        }
+    }
+
+    /// Statistics for a run configured by `options`.
+    fn for_run(options: &Options) -> PingStatistics {
+        PingStatistics {
+            trim_percent: options.trimmed_mean,
+            show_receive_path: options.verbose,
+            show_rate: options.window > 1 || options.interval < FAST_INTERVAL,
+            reflectable_ttl: Some(options.ttl)
+                .filter(|ttl| (1..=255).contains(ttl) && !COMMON_INITIAL_TTLS.contains(ttl))
+                .map(|ttl| ttl as u8),
+            ..PingStatistics::with_sample_limit(options.max_samples)
+        }
+    }
+
+    fn record_reply(&mut self, rtt: Duration) {
+        self.samples.push(rtt);
+        self.received += 1;
+        self.total_rtt += rtt;
+        self.min_rtt = self.min_rtt.min(rtt);
+        self.max_rtt = self.max_rtt.max(rtt);
+    }
+
+    fn record_ttl(&mut self, ttl: u8) {
+        self.ttl_replies += 1;
+        if self.reflectable_ttl == Some(ttl) {
+            self.reflected_ttls += 1;
+        }
+    }
+
+    /// Whether every reply came back with exactly the TTL we sent.
+    fn ttl_reflected(&self) -> bool {
+        self.ttl_replies > 0 && self.reflected_ttls == self.ttl_replies
+    }
+
+    fn record_drops(&mut self, reply: &Reply) {
+        if let Some(drops) = reply.rx_drops {
+            self.rx_drops = self.rx_drops.max(drops);
+        }
+    }
+
+    /// Records the local parts of a reply's RTT: the send call and the
+    /// delivery delay.
+    fn record_overhead(&mut self, reply: &Reply) {
+        if let Some(delivery) = reply.delivery {
+            self.total_delivery += delivery;
+            self.deliveries += 1;
+        }
+        if let Some(send) = reply.send {
+            self.total_send += send;
+            self.sends += 1;
+            self.max_send = self.max_send.max(send);
+        }
+    }
+
+    /// Mean time replies waited in the kernel before ring read them.
+    fn average_delivery(&self) -> Option<Duration> {
+        self.total_delivery.checked_div(self.deliveries)
+    }
+
+    /// Mean time the send call took for probes that were answered.
+    fn average_send(&self) -> Option<Duration> {
+        self.total_send.checked_div(self.sends)
+    }
+
+    /// Adds another run's counts to these, as `--repeat-runs` totals them.
+    /// The other run's RTT samples join this one's, within its sample limit.
+    fn absorb(&mut self, run: &PingStatistics) {
+        self.sent += run.sent;
+        self.received += run.received;
+        self.min_rtt = self.min_rtt.min(run.min_rtt);
+        self.max_rtt = self.max_rtt.max(run.max_rtt);
+        self.total_rtt += run.total_rtt;
+        self.retried += run.retried;
+        self.recovered += run.recovered;
+        self.rtt_violations += run.rtt_violations;
+        self.ttl_warnings += run.ttl_warnings;
+        self.ttl_replies += run.ttl_replies;
+        self.reflected_ttls += run.reflected_ttls;
+        self.other_responders += run.other_responders;
+        for (error, count) in &run.icmp_errors {
+            match self.icmp_errors.iter_mut().find(|(known, _)| known == error) {
+                Some((_, total)) => *total += count,
+                None => self.icmp_errors.push((*error, *count)),
+            }
+        }
+        // Each run opens its own socket, so its drop counter starts over.
+        self.rx_drops += run.rx_drops;
+        self.reordered += run.reordered;
+        self.duplicates += run.duplicates;
+        self.crc_mismatches += run.crc_mismatches;
+        self.path_mtu = match (self.path_mtu, run.path_mtu) {
+            (Some(ours), Some(theirs)) => Some(ours.min(theirs)),
+            (ours, theirs) => ours.or(theirs),
+        };
+        self.truncated += run.truncated;
+        self.short_replies += run.short_replies;
+        self.late_replies += run.late_replies;
+        self.anomalies += run.anomalies;
+        self.total_delivery += run.total_delivery;
+        self.deliveries += run.deliveries;
+        self.total_send += run.total_send;
+        self.sends += run.sends;
+        self.max_send = self.max_send.max(run.max_send);
+        self.elapsed += run.elapsed;
+        for rtt in run.samples.values() {
+            self.samples.push(*rtt);
+        }
+        self.exit_reason = run.exit_reason;
+    }
+
+    /// Probes sent and replies received per second of the run's wall-clock time.
+    fn rates(&self) -> Option<(f64, f64)> {
+        let seconds = self.elapsed.as_secs_f64();
+        (seconds > 0.0 && self.sent > 0).then(|| (f64::from(self.sent) / seconds, f64::from(self.received) / seconds))
+    }
+
+    fn record_error(&mut self, error: IcmpError) {
+        match self.icmp_errors.iter_mut().find(|(known, _)| *known == error) {
+            Some((_, count)) => *count += 1,
+            None => self.icmp_errors.push((error, 1)),
+        }
+    }
+
+    /// The most common reason probes failed: an ICMP error kind, or silence.
+    fn failure_reason(&self) -> (&'static str, u32) {
+        let errors: u32 = self.icmp_errors.iter().map(|(_, count)| count).sum();
+        let silent = self.lost().saturating_sub(errors);
+
+        self.icmp_errors
+            .iter()
+            .map(|(error, count)| (error.describe(), *count))
+            .filter(|(_, count)| *count >= silent)
+            .max_by_key(|(_, count)| *count)
+            .unwrap_or(("No response", silent))
+    }
+
+    /// Probes without a reply, not counting those answered during `--grace`.
+    fn lost(&self) -> u32 {
+        self.sent.saturating_sub(self.received + self.late_replies)
+    }
+
+    fn loss_percent(&self) -> f64 {
+        if self.sent > 0 {
+            100.0 * self.lost() as f64 / self.sent as f64
+        } else {
+            0.0
+        }
+    }
+
+    fn average_rtt(&self) -> Option<Duration> {
+        self.total_rtt.checked_div(self.received)
+    }
+
+    /// Fastest and slowest reply, or `None` before the first one, so the
+    /// `Duration::MAX` that `min_rtt` starts from never gets printed.
+    fn rtt_range(&self) -> Option<(Duration, Duration)> {
+        (self.received > 0).then_some((self.min_rtt, self.max_rtt))
+    }
+
+    fn trimmed_mean_rtt(&self) -> Option<Duration> {
+        self.samples.trimmed_mean(self.trim_percent?)
+    }
+}
+
+/// Difference between wall-clock and monotonic progress treated as a jump.
+const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Notices wall-clock steps (NTP corrections, VM pauses) by comparing how far
+/// the system clock moved against the monotonic clock between iterations.
+struct ClockWatch {
+    monotonic: Instant,
+    wall: SystemTime,
+    warned: bool,
+}
+
+impl ClockWatch {
+    fn new() -> ClockWatch {
+        ClockWatch {
+            monotonic: Instant::now(),
+            wall: SystemTime::now(),
+            warned: false,
+        }
+    }
+
+    /// Returns the size of the jump in seconds the first time one is seen.
+    fn check(&mut self) -> Option<f64> {
+        let monotonic = Instant::now();
+        let wall = SystemTime::now();
+        let expected = monotonic.duration_since(self.monotonic).as_secs_f64();
+        let actual = match wall.duration_since(self.wall) {
+            Ok(forward) => forward.as_secs_f64(),
+            Err(backward) => -backward.duration().as_secs_f64(),
+        };
+        self.monotonic = monotonic;
+        self.wall = wall;
+
+        let jump = actual - expected;
+        if self.warned || jump.abs() < CLOCK_JUMP_THRESHOLD.as_secs_f64() {
+            return None;
+        }
+        self.warned = true;
+        Some(jump)
+    }
+}
+
+struct ReceivedPacket {
+    len: usize,
+    source: Option<SocketAddr>,
+    tos: Option<u8>,
+    ttl: Option<u8>,
+    rx_drops: Option<u32>,
+    /// The datagram was longer than the buffer and the rest was discarded.
+    truncated: bool,
+    /// How long the datagram waited between the kernel receiving it and the
+    /// read returning (Linux `SO_TIMESTAMPNS`).
+    delivery: Option<Duration>,
+}
+
+fn get_argument(args: &[String], option: &str, default: i32) -> i32 {
+    if let Some(index) = args.iter().position(|arg| arg == option) {
+        if let Some(value) = args.get(index + 1) {
+            if let Ok(num) = value.parse::<i32>() {
+                return num;
+            }
+        }
+    }
+    default
+}
+
+fn get_string_argument<'a>(args: &'a [String], option: &str) -> Option<&'a str> {
+    let index = args.iter().position(|arg| arg == option)?;
+    args.get(index + 1).map(String::as_str)
+}
+
+/// The value of `option` as a decimal or `0x`-prefixed hexadecimal number,
+/// as capture tools show header fields. `Err` carries a value that is not
+/// a number or does not fit in `T`.
+fn get_field_argument<'a, T: TryFrom<u64>>(args: &'a [String], option: &str) -> Result<Option<T>, &'a str> {
+    let Some(value) = get_string_argument(args, option) else {
+        return Ok(None);
+    };
+    let number = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    };
+    number.ok().and_then(|number| T::try_from(number).ok()).map(Some).ok_or(value)
+}
+
+fn get_optional_argument(args: &[String], option: &str) -> Option<i32> {
+    let index = args.iter().position(|arg| arg == option)?;
+    args.get(index + 1)?.parse::<i32>().ok()
+}
+
+fn run_ring(target: IpAddr, options: &Options) -> PingStatistics {
+    let &Options { mut count, packet_size, timeout, ttl, .. } = options;
+    let (socket, kind) = match create_socket(target, options) {
+        Ok(created) => created,
+        Err(e) => {
+            log_error!("failed to create socket for {}: {}", target, e);
+            report_error(json::error("socket", target, &e), format!("Failed to create socket: {}", e));
+            std::process::exit(1);
+        }
+    };
+    if options.verbose {
+        print_socket_details(target, kind);
+    }
+    bind_identifier(&socket, kind, target, options);
+    if let Some(source) = options.spoof_source {
+        if let Err(e) = include_ip_header(&socket, kind, target) {
+            report_error(json::error("socket", target, &e), format!("Cannot spoof the source address: {}", e));
+            std::process::exit(1);
+        }
+        say!("Spoofing source {}: replies go to that address, so probes time out unless it routes back here.", source);
+    }
+    if options.bad_checksum {
+        if let Err(e) = corruptible_checksum(kind, target) {
+            report_error(json::error("socket", target, &e), format!("Cannot send bad checksums: {}", e));
+            std::process::exit(1);
+        }
+        say!("Warning: --bad-checksum corrupts every echo request on purpose; a correct stack drops them, so expect timeouts.");
+    }
+    if options.show_local_port {
+        show_local_port(&socket, kind, target, options);
+    }
+    if options.randomize_id && kind == SocketKind::Dgram {
+        say!("Note: the kernel assigns identifiers on datagram sockets, so --randomize-id-per-packet has no effect.");
+    }
+    log_info!(
+        "starting run against {} (count={}, size={}, timeout={}ms, ttl={})",
+        target,
+        describe_count(count),
+        packet_size,
+        timeout,
+        ttl
+    );
+
+    let dest_addr = match target {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V4(ip), 0),
+        IpAddr::V6(ip) => SocketAddr::new(IpAddr::V6(ip), 0),
+    };
+
+    let mut stats = PingStatistics::for_run(options);
+    let started = Instant::now();
+    let mut reachable = None;
+    let mut identifier = base_identifier(options);
+    let mut sequence = initial_sequence(options);
+    let mut recent_ids = VecDeque::with_capacity(RECENT_ID_LIMIT);
+    let mut rng = rand::thread_rng();
+    let mut payload_rng = payload_rng(options);
+    // The status line redraws itself in place, which only makes sense on a terminal.
+    let status_line = (options.graph || options.live_stats) && io::stdout().is_terminal() && !output::is_quiet();
+    let mut graph = (options.graph && status_line).then(|| Sparkline::new(GRAPH_WIDTH));
+    let mut clock = ClockWatch::new();
+    let mut previous_rtt: Option<Duration> = None;
+    let console = if options.interactive { Console::open() } else { None };
+    if options.interactive {
+        match console {
+            Some(_) => say!("Interactive: p pause, r resume, s stats, q quit."),
+            None => say!("Note: --interactive needs a terminal on stdin; ignoring it."),
+        }
+    }
+
+    // Whether each of the last `--window-size` probes got a reply.
+    let mut recent_outcomes = VecDeque::new();
+    // Probes that timed out, whose replies `--grace` still waits for.
+    let mut timed_out = VecDeque::new();
+    // Probes in a row that failed with a socket error rather than a timeout.
+    let mut socket_errors = 0;
+    // Every host that answered a collection, with its fastest reply.
+    let mut discovered: Vec<(IpAddr, Duration)> = Vec::new();
+    if collects_responders(target, options) {
+        say!("Collecting every responder within {}ms of each request.", timeout);
+    }
+
+    if options.exit_reason {
+        catch_interrupt();
+    }
+
+    while count != Some(0) {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            stats.exit_reason = ExitReason::Interrupted;
+            break;
+        }
+        let probe_started = Instant::now();
+        sequence = next_sequence(sequence, options);
+        if options.randomize_id {
+            identifier = rng.gen();
+        }
+        if !recent_ids.contains(&identifier) {
+            if recent_ids.len() == RECENT_ID_LIMIT {
+                recent_ids.pop_front();
+            }
+            recent_ids.push_back(identifier);
+        }
+        let mut packet = create_icmp_packet(packet_size, target, identifier, sequence, payload_rng.as_mut(), options.payload_crc);
+        if options.bad_checksum {
+            break_checksum(&mut packet);
+        }
+        let mut probe_rtt = None;
+        let mut stop = false;
+
+        if status_line {
+            print!("\r\x1b[K");
+        }
+
+        if let Some(jump) = clock.check() {
+            log_warn!("system clock jumped by {:+.3}s", jump);
+            say!(
+                "Warning: the system clock jumped by {:+.1}s during the run; RTTs are unaffected but wall-clock times may be inconsistent.",
+                jump
+            );
+        }
+
+        if collects_responders(target, options) {
+            // Extra requests in the same collection get sequence numbers of
+            // their own, so each reply is timed against the request it answers.
+            let mut packets = vec![packet];
+            for _ in 1..options.discover_count {
+                sequence = next_sequence(sequence, options);
+                packets.push(create_icmp_packet(packet_size, target, identifier, sequence, payload_rng.as_mut(), options.payload_crc));
+            }
+            // Every request counts as sent; the loop counts the first one.
+            stats.sent += packets.len() as u32 - 1;
+            match send_and_collect_responders(&socket, kind, &packets, &dest_addr, options) {
+                Ok(Collection { responders, answers }) if !responders.is_empty() => {
+                    // Statistics follow the fastest responder to each request,
+                    // so a request counts as answered if anyone answered it.
+                    for rtt in answers.iter().flatten() {
+                        stats.record_reply(*rtt);
+                    }
+                    probe_rtt = answers.iter().flatten().min().copied();
+
+                    for responder in &responders {
+                        let mut line = format!("Reply from {}: bytes={} time={}", responder.address, packet_size, output::rtt(responder.rtt));
+                        if responder.truncated {
+                            stats.truncated += 1;
+                            line = highlight(&format!("{} [truncated]", line));
+                        }
+                        say!("{}", line);
+                        match discovered.iter_mut().find(|(known, _)| *known == responder.address) {
+                            Some((_, best)) => *best = (*best).min(responder.rtt),
+                            None => discovered.push((responder.address, responder.rtt)),
+                        }
+                    }
+                }
+                Ok(_) => say!("Request timed out."),
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    report_permission_denied(target, &e);
+                    stats.exit_reason = ExitReason::PermissionDenied;
+                    break;
+                }
+                Err(e) => {
+                    log_warn!("socket error while probing {}: {}", target, e);
+                    say!("Request timed out.");
+                }
+            }
+        } else {
+            let mut result = send_and_receive_ring(&socket, kind, &packet, &dest_addr, &recent_ids, options);
+
+            let mut attempts = 0;
+            while attempts < options.retries && matches!(&result, Err(e) if is_timeout(e)) {
+                // Retransmissions use a fresh sequence number so a late reply
+                // to the earlier attempt is not mistaken for this one.
+                attempts += 1;
+                sequence = next_sequence(sequence, options);
+                log_info!("retrying probe to {} with seq={} (attempt {} of {})", target, sequence, attempts, options.retries);
+                let mut packet = create_icmp_packet(packet_size, target, identifier, sequence, payload_rng.as_mut(), options.payload_crc);
+                if options.bad_checksum {
+                    break_checksum(&mut packet);
+                }
+                result = send_and_receive_ring(&socket, kind, &packet, &dest_addr, &recent_ids, options);
+            }
+            if attempts > 0 {
+                stats.retried += 1;
+                if result.is_ok() {
+                    stats.recovered += 1;
+                }
+            }
+
+            match &result {
+                Ok(reply) => log_debug!("reply from {} in {:?}", target, reply.rtt),
+                Err(e) if is_timeout(e) => {
+                    log_debug!("probe to {} timed out", target)
+                }
+                Err(e) if probe_error(e).is_some() => log_info!("probe to {} failed: {}", target, e),
+                // Privileges dropped or a sandbox tightened mid-run: every
+                // later probe would fail the same way, so stop instead of
+                // reporting them as losses.
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    report_permission_denied(target, e);
+                    stats.exit_reason = ExitReason::PermissionDenied;
+                    break;
+                }
+                Err(e) => log_warn!("socket error while probing {}: {}", target, e),
+            }
+
+            // Timeouts and ICMP errors mean the probe went out; anything else
+            // is a local failure such as a downed interface.
+            let socket_error = result.as_ref().err().filter(|e| !is_timeout(e) && probe_error(e).is_none());
+            match (options.pause_on_error, socket_error) {
+                (pause, Some(e)) => {
+                    socket_errors += 1;
+                    if let Some(pause) = pause.filter(|_| socket_errors == ERROR_STREAK) {
+                        log_warn!("{} consecutive socket errors while probing {}; pausing", socket_errors, target);
+                        say!(
+                            "{} probes in a row failed to go out ({}); pausing for {}ms, backing off while the errors persist.",
+                            socket_errors,
+                            e,
+                            pause.as_millis()
+                        );
+                    }
+                }
+                (Some(_), None) if socket_errors >= ERROR_STREAK => {
+                    log_info!("probes to {} are going out again after {} socket errors", target, socket_errors);
+                    say!("Probes are going out again after {} failed attempts; resuming.", socket_errors);
+                    socket_errors = 0;
+                }
+                (_, None) => socket_errors = 0,
+            }
+
+            if reachable != Some(result.is_ok()) {
+                log_info!("{} is now {}", target, if result.is_ok() { "reachable" } else { "unreachable" });
+                // The first probe only sets the baseline; the run summary covers it.
+                if reachable.is_some() {
+                    report::state(target, result.is_ok());
+                    webhook::post(&json::state(target, result.is_ok()));
+                }
+                reachable = Some(result.is_ok());
+            }
+
+            if let Ok(reply) = result {
+                let rtt = reply.rtt;
+                probe_rtt = Some(rtt);
+                stats.record_reply(rtt);
+                stats.record_drops(&reply);
+                stats.record_overhead(&reply);
+
+                if reply.source != target {
+                    stats.other_responders += 1;
+                }
+                let mut line = format_reply(target, &reply, options);
+                // A timeout keeps the last baseline, so the first reply after
+                // a loss is compared with the one before it.
+                append_rtt_change(&mut line, previous_rtt, rtt, options);
+                previous_rtt = Some(rtt);
+
+                if reply.payload_crc == Some(false) {
+                    stats.crc_mismatches += 1;
+                    line = highlight(&format!("{} [payload CRC mismatch]", line));
+                }
+                if reply.truncated {
+                    stats.truncated += 1;
+                    line = highlight(&format!("{} [truncated]", line));
+                }
+                let short = short_reply(&reply, options);
+                if let Some(expected) = short {
+                    stats.short_replies += 1;
+                    line = highlight(&format!("{} [short reply: {} of {} bytes]", line, reply.bytes, expected));
+                }
+
+                let anomaly = stats.anomaly_threshold.filter(|threshold| rtt > *threshold);
+                if let Some(threshold) = anomaly {
+                    stats.anomalies += 1;
+                    line = highlight(&format!("{} [anomaly: above baseline {}]", line, output::rtt(threshold)));
+                }
+
+                if let Some(max_rtt) = options.max_rtt.filter(|max_rtt| rtt > *max_rtt) {
+                    stats.rtt_violations += 1;
+                    line = highlight(&format!("{} [exceeds {}]", line, output::rtt(max_rtt)));
+                    if options.abort_on_max_rtt {
+                        log_warn!("aborting run against {} after a reply exceeded the RTT limit", target);
+                        stats.exit_reason = ExitReason::RttLimit;
+                        stop = true;
+                    }
+                }
+                say!("{}", line);
+                check_reply_headers(&mut stats, &reply, options);
+
+                report::reply(target, &reply);
+                let mut record = json::reply(target, &reply, ttl_warning(&reply, options));
+                if anomaly.is_some() {
+                    record = record.bool("anomaly", true);
+                }
+                if let Some(expected) = short {
+                    record = record.int("expected_bytes", expected as i64);
+                }
+                ndjson::write(&record);
+            } else if let Some(error) = result.as_ref().err().and_then(probe_error) {
+                stats.record_error(error.error);
+                say!("Reply from {}{}: {}.", error.from, asn::annotate(error.from), error.reason());
+                if let Some(mtu) = error.mtu {
+                    note_path_mtu(target, &mut stats, mtu);
+                }
+                report::timeout(target, sequence);
+                ndjson::write(&json::icmp_error(target, sequence, error));
+            } else {
+                say!("Request timed out.");
+                report::timeout(target, sequence);
+                if options.grace.is_some() {
+                    if timed_out.len() == GRACE_TRACKED {
+                        timed_out.pop_front();
+                    }
+                    timed_out.push_back(sequence);
+                }
+                match result.as_ref().err().filter(|e| !is_timeout(e)) {
+                    Some(e) => ndjson::write(&json::error("probe", target, e).int("seq", sequence)),
+                    None => ndjson::write(&json::timeout(target, sequence)),
+                }
+            }
+        }
+
+        stats.sent += 1;
+
+        if let Some((_, k)) = options.warmup.filter(|(warmup, _)| stats.sent == *warmup) {
+            if status_line {
+                print!("\r\x1b[K");
+            }
+            stats.anomaly_threshold = end_warmup(target, &stats, k);
+        }
+
+        if let Some((max_loss, size)) = options.stop_if_window_loss {
+            if recent_outcomes.len() == size {
+                recent_outcomes.pop_front();
+            }
+            recent_outcomes.push_back(probe_rtt.is_some());
+            let lost = recent_outcomes.iter().filter(|received| !**received).count();
+            let loss = 100.0 * lost as f64 / size as f64;
+            if recent_outcomes.len() == size && loss > max_loss {
+                log_warn!("loss over the last {} probes to {} reached {:.0}%", size, target, loss);
+                say!("Loss over the last {} probes is {:.0}%, above the allowed {}%; stopping.", size, loss, max_loss);
+                stats.exit_reason = ExitReason::WindowLoss;
+                stop = true;
+            }
+        }
+
+        // Periodic snapshots cover the whole run so far; nothing is reset.
+        if let Some(every) = options.summary_every {
+            if probe_rtt.is_some() && stats.received.is_multiple_of(every) {
+                print_statistics(target, &stats);
+            }
+        }
+
+        if status_line {
+            let mut parts = Vec::new();
+            if let Some(graph) = graph.as_mut() {
+                graph.push(probe_rtt);
+                parts.push(graph.render());
+            }
+            if options.live_stats {
+                parts.push(live_stats_line(&stats));
+            }
+            print!("{}", parts.join("  "));
+            let _ = io::stdout().flush();
+        }
+
+        if stop {
+            break;
+        }
+
+        count_down(&mut count);
+        if count != Some(0) {
+            let slot_start = pacing_start(probe_started, probe_rtt.is_none(), options);
+            let interval = match options.pause_on_error.filter(|_| socket_errors >= ERROR_STREAK) {
+                Some(pause) => error_pause(pause, socket_errors).max(options.interval),
+                None => options.interval,
+            };
+            match &console {
+                Some(console) => {
+                    if status_line {
+                        print!("\r\x1b[K");
+                    }
+                    if wait_for_next_probe(console, target, &stats, slot_start + interval) {
+                        stats.exit_reason = ExitReason::Quit;
+                        break;
+                    }
+                }
+                None if options.exit_reason => sleep_unless_interrupted(slot_start + interval),
+                None => sleep_until_next_probe(slot_start, interval),
+            }
+        }
+    }
+
+    if status_line {
+        say!();
+    }
+
+    stats.elapsed = started.elapsed();
+    if let Some(grace) = options.grace.filter(|_| stats.exit_reason.waits_for_stragglers()) {
+        stats.late_replies = collect_late_replies(&socket, kind, target, &recent_ids, &mut timed_out, grace, options);
+    }
+
+    log_info!("run against {} finished: sent={} received={}", target, stats.sent, stats.received);
+
+    report::summary(target, &stats);
+    ndjson::write(&json::summary(target, &stats));
+    webhook::post(&json::summary(target, &stats));
+    print_statistics(target, &stats);
+    print_histogram(&stats, options);
+    if collects_responders(target, options) {
+        print_responders(&mut discovered);
+    }
+    if options.bad_checksum && stats.sent > 0 {
+        report_bad_checksum(&stats);
+    }
+    if options.exit_reason {
+        say!("exited: {}", stats.exit_reason.describe());
+    }
+    stats
+}
+
+/// Appends the `--show-delta` change and the `--trend` arrow from the
+/// `previous` reply's RTT.
+fn append_rtt_change(line: &mut String, previous: Option<Duration>, rtt: Duration, options: &Options) {
+    let Some(previous) = previous else {
+        return;
+    };
+    if options.show_delta {
+        let delta = rtt.as_secs_f64() - previous.as_secs_f64();
+        line.push_str(&format!(" ({})", output::rtt_delta(delta)));
+    }
+    if let Some(tolerance) = options.trend {
+        line.push(' ');
+        line.push(trend_arrow(previous, rtt, tolerance));
+    }
+}
+
+/// Records the TTL of `reply` and prints the warnings `--warn-on-ttl-below`
+/// and `--tos-echo` ask for about its IP header.
+fn check_reply_headers(stats: &mut PingStatistics, reply: &Reply, options: &Options) {
+    if let Some(received_ttl) = reply.ttl {
+        stats.record_ttl(received_ttl);
+    }
+    if let (Some(minimum), Some(received_ttl)) = (options.warn_on_ttl_below, reply.ttl) {
+        if received_ttl < minimum {
+            stats.ttl_warnings += 1;
+            say!("    Warning: reply TTL {} is below {}; the path may be unusually long or looping", received_ttl, minimum);
+        }
+    }
+
+    if let (true, Some(tos)) = (options.tos_echo, reply.tos) {
+        let expected = options.tos.unwrap_or(0);
+        if tos != expected {
+            say!("    Warning: TOS changed in transit (sent 0x{:02x}, received 0x{:02x})", expected, tos);
+        }
+    }
+}
+
+/// Prints the baseline the `--warmup-count` probes established and returns
+/// the RTT above which later replies count as anomalies: the warmup average
+/// plus `k` times its mdev.
+fn end_warmup(target: IpAddr, stats: &PingStatistics, k: f64) -> Option<Duration> {
+    let (Some(average), Some(mdev)) = (stats.average_rtt(), stats.samples.mdev()) else {
+        say!("--- No replies during the {}-probe warmup; there is no baseline to watch against.", stats.sent);
+        return None;
+    };
+    let threshold = average + mdev.mul_f64(k);
+    say!(
+        "--- Baseline from {} warmup probes: {}/{} replies ({:.0}% loss), avg={}, mdev={}; flagging replies above {} (avg + {}x mdev)",
+        stats.sent,
+        stats.received,
+        stats.sent,
+        stats.loss_percent(),
+        output::rtt(average),
+        output::rtt(mdev),
+        output::rtt(threshold),
+        k
+    );
+    ndjson::write(&json::baseline(target, stats, mdev, threshold));
+    Some(threshold)
+}
+
+/// Says what answers to `--bad-checksum` requests mean.
+fn report_bad_checksum(stats: &PingStatistics) {
+    if stats.received > 0 {
+        say!(
+            "    Bad checksum: {} of {} corrupted requests were answered; checksum offload or a middlebox rewrote them on the way, or the target does not verify checksums.",
+            stats.received, stats.sent
+        );
+    } else {
+        say!("    Bad checksum: none of the {} corrupted requests were answered; they were dropped as they should be.", stats.sent);
+    }
+}
+
+/// The pause before the next probe after `errors` socket errors in a row:
+/// `pause` at first, doubling with every further error up to `MAX_ERROR_PAUSE`.
+fn error_pause(pause: Duration, errors: u32) -> Duration {
+    let doublings = errors.saturating_sub(ERROR_STREAK).min(16);
+    pause.saturating_mul(1 << doublings).min(MAX_ERROR_PAUSE)
+}
+
+/// Lists every host that answered a broadcast or multicast run once,
+/// fastest first, as a host-discovery summary.
+fn print_responders(discovered: &mut [(IpAddr, Duration)]) {
+    discovered.sort_by_key(|(source, rtt)| (*rtt, *source));
+    say!("    Unique responders: {}", discovered.len());
+    let width = discovered.iter().map(|(source, _)| source.to_string().len()).max().unwrap_or(0);
+    for (source, rtt) in discovered.iter() {
+        say!("        {:<width$}  {}", source.to_string(), output::rtt(*rtt));
+    }
+}
+
+/// Records an MTU learned from a too-big error and, when it lowers what was
+/// known, says which payload size would fit.
+fn note_path_mtu(target: IpAddr, stats: &mut PingStatistics, mtu: u32) {
+    if stats.path_mtu.is_some_and(|known| known <= mtu) {
+        return;
+    }
+    stats.path_mtu = Some(mtu);
+    say!("    Path MTU to {} is {} bytes; payloads up to -s {} fit without fragmenting.", target, mtu, max_payload(target, mtu));
+}
+
+/// Largest echo payload that fits in `mtu` with a bare IP header.
+fn max_payload(target: IpAddr, mtu: u32) -> u32 {
+    let ip_header = if target.is_ipv4() { 20 } else { 40 };
+    mtu.saturating_sub(ip_header + ICMP_HEADER_LEN as u32)
+}
+
+/// Warns once, before the run, when the largest `-s` will not fit in the MTU
+/// of the interface the route to `target` leaves through. Fragmenting can be
+/// what the user wants, so this never stops the run.
+fn warn_if_fragmented(target: IpAddr, packet_sizes: &[usize]) {
+    let Some(mtu) = route_mtu(target) else {
+        log_debug!("cannot query the MTU of the route to {}", target);
+        return;
+    };
+    let largest = packet_sizes.iter().copied().max().unwrap_or(0);
+    if largest as u64 > u64::from(max_payload(target, mtu)) {
+        say!(
+            "Warning: -s {} does not fit the {}-byte MTU towards {} (at most -s {}); packets will be fragmented, or dropped where fragmenting is not allowed.",
+            largest,
+            mtu,
+            target,
+            max_payload(target, mtu)
+        );
+    }
+}
+
+/// MTU the kernel would use towards `target`. Connecting a UDP socket picks
+/// the route without sending anything, and IP_MTU then reports it.
+#[cfg(target_os = "linux")]
+fn route_mtu(target: IpAddr) -> Option<u32> {
+    let (local, level, option) = match target {
+        IpAddr::V4(_) => (IpAddr::V4(Ipv4Addr::UNSPECIFIED), libc::IPPROTO_IP, libc::IP_MTU),
+        IpAddr::V6(_) => (IpAddr::V6(Ipv6Addr::UNSPECIFIED), libc::IPPROTO_IPV6, libc::IPV6_MTU),
+    };
+    let socket = std::net::UdpSocket::bind(SocketAddr::new(local, 0)).ok()?;
+    socket.connect(SocketAddr::new(target, 9)).ok()?;
+
+    let mut mtu: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(socket.as_raw_fd(), level, option, &mut mtu as *mut libc::c_int as *mut libc::c_void, &mut length)
+    };
+    (result == 0 && mtu > 0).then_some(mtu as u32)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn route_mtu(_target: IpAddr) -> Option<u32> {
+    None
+}
+
+/// Prints the identifier replies will be matched on. A datagram socket gets
+/// its local port, which the kernel uses as the identifier, on the first
+/// send; binding to port 0 here makes it pick one early so it can be shown.
+fn show_local_port(socket: &Socket, kind: SocketKind, target: IpAddr, options: &Options) {
+    if kind == SocketKind::Raw {
+        say!("Raw socket: no local port; echo requests carry identifier {}.", base_identifier(options));
+        return;
+    }
+    let unspecified = match target {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let bound = socket.local_addr().ok().and_then(|local| local.as_socket()).filter(|local| local.port() != 0);
+    let local = match bound {
+        Some(local) => Ok(local),
+        None => socket
+            .bind(&SocketAddr::new(unspecified, 0).into())
+            .and_then(|_| socket.local_addr())
+            .and_then(|local| local.as_socket().ok_or_else(|| io::Error::other("not an IP socket address"))),
+    };
+    match local {
+        Ok(local) => say!("Datagram socket bound to local port {} (the identifier replies are matched on).", local.port()),
+        Err(e) => say!("Could not read the local port of the datagram socket: {}", e),
+    }
+}
+
+fn report_permission_denied(target: IpAddr, error: &io::Error) {
+    log_error!("permission denied while probing {}: {}", target, error);
+    report_error(
+        json::error("permission", target, error),
+        format!("Permission denied while probing {} ({}); stopping the run.", target, error),
+    );
+}
+
+/// Emits `record` for an error, and prints `message` as well unless `--json`
+/// has replaced the text output, so a JSON stream stays parseable on failure.
+fn report_error(record: json::Object, message: String) {
+    if !ndjson::on_stdout() {
+        println!("{}", message);
+    }
+    ndjson::write(&record);
+}
+
+/// Whether `rtt` rose, fell or stayed within `tolerance` of `previous`.
+fn trend_arrow(previous: Duration, rtt: Duration, tolerance: Duration) -> char {
+    if rtt > previous + tolerance {
+        '\u{2191}'
+    } else if rtt + tolerance < previous {
+        '\u{2193}'
+    } else {
+        '\u{2192}'
+    }
+}
+
+/// Makes a flagged reply line stand out: bold red on a terminal, while the
+/// bracketed note keeps it distinct when output is redirected.
+fn highlight(line: &str) -> String {
+    if io::stdout().is_terminal() {
+        format!("\x1b[1;31m{}\x1b[0m", line)
+    } else {
+        line.to_string()
+    }
+}
+
+/// Whether the reply fell below `--warn-on-ttl-below`, or `None` when that
+/// option is off.
+fn ttl_warning(reply: &Reply, options: &Options) -> Option<bool> {
+    options.warn_on_ttl_below.map(|minimum| reply.ttl.is_some_and(|ttl| ttl < minimum))
+}
+
+/// Builds the per-reply line, either from `--format` or the default layout.
+fn format_reply(target: IpAddr, reply: &Reply, options: &Options) -> String {
+    let reply_ttl = reply.ttl.map_or(options.ttl, i32::from);
+    if let Some(template) = &options.format {
+        return template.render(reply, reply_ttl);
+    }
+
+    let tos_field = match (options.tos_echo, reply.tos) {
+        (true, Some(tos)) => format!(" TOS=0x{:02x}", tos),
+        _ => String::new(),
+    };
+    // Anycast, NAT or an ICMP proxy can answer on the target's behalf.
+    let responder_field = if reply.source != target {
+        format!(" (target {})", target)
+    } else {
+        String::new()
+    };
+    // `-v` splits the RTT into the send call, the network and the delivery
+    // delay, or into the send call and the wait without kernel timestamps.
+    let mut timing = Vec::new();
+    if options.verbose {
+        if let Some(send) = reply.send {
+            timing.push(format!("send={}", output::rtt(send)));
+        }
+        match (reply.network_rtt(), reply.delivery, reply.send) {
+            (Some(network), Some(delivery), _) => {
+                timing.push(format!("network={}", output::rtt(network)));
+                timing.push(format!("delivery={}", output::rtt(delivery)));
+            }
+            (_, _, Some(send)) => timing.push(format!("wait={}", output::rtt(reply.rtt.saturating_sub(send)))),
+            _ => {}
+        }
+    }
+    let receive_path_field = if timing.is_empty() { String::new() } else { format!(" ({})", timing.join(" ")) };
+    format!(
+        "Reply from {}{}: bytes={} time={} TTL={}{}{}{}",
+        reply.source,
+        asn::annotate(reply.source),
+        options.packet_size,
+        output::rtt(reply.rtt),
+        reply_ttl,
+        tos_field,
+        responder_field,
+        receive_path_field
+    )
+}
+
+/// Sequence numbers handed out under `--global-seq`, shared by every target
+/// and run in the process.
+static GLOBAL_SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+/// The identifier echo requests carry: `--id`, or the process ID.
+fn base_identifier(options: &Options) -> u16 {
+    options.identifier.unwrap_or(std::process::id() as u16)
+}
+
+/// The sequence number to step from, so the first probe gets `--start-seq`
+/// (or 1) and later ones wrap at 65535 as usual.
+fn initial_sequence(options: &Options) -> u16 {
+    options.start_seq.unwrap_or(1).wrapping_sub(1)
+}
+
+/// Datagram sockets carry their local port as the echo identifier, so binding
+/// to the `--id` value is how they send it.
+fn bind_identifier(socket: &Socket, kind: SocketKind, target: IpAddr, options: &Options) {
+    let (Some(identifier), SocketKind::Dgram) = (options.identifier, kind) else {
+        return;
+    };
+    let unspecified = match target {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    if let Err(e) = socket.bind(&SocketAddr::new(unspecified, identifier).into()) {
+        say!("Note: cannot use identifier {} on the datagram socket ({}); the kernel picks one instead.", identifier, e);
+    }
+}
+
+/// The sequence number for the next probe after `current`: per target by
+/// default, or unique across all targets with `--global-seq` so merged
+/// NDJSON or packet captures can be correlated.
+fn next_sequence(current: u16, options: &Options) -> u16 {
+    if options.global_seq {
+        GLOBAL_SEQUENCE.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+    } else {
+        current.wrapping_add(1)
+    }
+}
+
+/// Set by the SIGINT handler `--exit-reason` installs.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Lets the first Ctrl-C end the run after the probe in flight, so the
+/// summary and exit reason still print. A second one kills ring as usual.
+fn catch_interrupt() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, note_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn note_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+/// Sleeps until `deadline` in short steps, returning early on Ctrl-C.
+fn sleep_unless_interrupted(deadline: Instant) {
+    while !INTERRUPTED.load(Ordering::Relaxed) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        sleep(left.min(INTERRUPT_POLL));
+    }
+}
+
+/// Sleeps for whatever is left of `interval` since `started`, so the send
+/// cadence stays at the configured interval however long the probe took.
+fn sleep_until_next_probe(started: Instant, interval: Duration) {
+    sleep(interval.saturating_sub(started.elapsed()));
+}
+
+/// When the interval before the next probe starts counting. A probe normally
+/// takes one interval slot from when it was sent, but with
+/// `--loss-pacing interval` a lost one waits the full interval after its
+/// timeout instead.
+fn pacing_start(started: Instant, lost: bool, options: &Options) -> Instant {
+    match (options.loss_pacing, lost) {
+        (LossPacing::Interval, true) => Instant::now(),
+        _ => started,
+    }
+}
+
+/// Handles console keys until the next probe is due. Returns `true` when the
+/// user asked to quit.
+fn wait_for_next_probe(console: &Console, target: IpAddr, stats: &PingStatistics, deadline: Instant) -> bool {
+    while let Some(key) = console.next_key(Some(deadline)) {
+        match key {
+            Key::Stats => print_statistics(target, stats),
+            Key::Quit => return true,
+            Key::Resume => {}
+            Key::Pause => {
+                say!("Paused; press r to resume or q to quit.");
+                loop {
+                    match console.next_key(None) {
+                        Some(Key::Stats) => print_statistics(target, stats),
+                        Some(Key::Quit) => return true,
+                        Some(Key::Pause) => {}
+                        Some(Key::Resume) | None => break,
+                    }
+                }
+                say!("Resumed.");
+                return false;
+            }
+        }
+    }
+    false
+}
+
+/// One-line summary for `--compact`, e.g. `8.8.8.8 ok loss=0% avg=12ms`.
+fn compact_summary(name: &str, stats: &PingStatistics) -> String {
+    match stats.average_rtt() {
+        Some(average) => format!("{} ok loss={:.0}% avg={}", name, stats.loss_percent(), output::rtt(average)),
+        None => format!("{} DOWN", name),
+    }
+}
+
+fn live_stats_line(stats: &PingStatistics) -> String {
+    let mut line = format!(
+        "sent={} recv={} loss={:.0}%",
+        stats.sent,
+        stats.received,
+        stats.loss_percent()
+    );
+    if let (Some(average), Some((min, max))) = (stats.average_rtt(), stats.rtt_range()) {
+        line.push_str(&format!(" min={} avg={} max={}", output::rtt(min), output::rtt(average), output::rtt(max)));
+    }
+    line
+}
+
+fn print_statistics(target: IpAddr, stats: &PingStatistics) {
+    say!("\nring statistics for {}:", target);
+    say!(
+        "    Packets: Sent = {}, Received = {}, Lost = {} ({:.0}% loss),",
+        stats.sent,
+        stats.received,
+        stats.lost(),
+        stats.loss_percent()
+    );
+
+    if let Some((sent, received)) = stats.rates().filter(|_| stats.show_rate) {
+        say!(
+            "    Rate: {:.1} probes/s sent, {:.1} replies/s received over {:.2}s",
+            sent,
+            received,
+            stats.elapsed.as_secs_f64()
+        );
+    }
+
+    if let Some(threshold) = stats.anomaly_threshold {
+        say!(
+            "    Anomalies: {} replies after the warmup were slower than the baseline allowed ({})",
+            stats.anomalies,
+            output::rtt(threshold)
+        );
+    }
+
+    if stats.late_replies > 0 {
+        say!("    Late replies: {} arrived after their timeout, during --grace; not counted as lost", stats.late_replies);
+    }
+
+    if !stats.icmp_errors.is_empty() {
+        let errors: Vec<String> = stats
+            .icmp_errors
+            .iter()
+            .map(|(error, count)| format!("{} {}", count, error.describe().to_lowercase()))
+            .collect();
+        say!("    ICMP errors: {}", errors.join(", "));
+    }
+
+    if stats.sent > 0 && stats.received + stats.late_replies == 0 {
+        let (reason, count) = stats.failure_reason();
+        say!("    Outcome: {} ({} of {} probes)", reason, count, stats.sent);
+    }
+
+    if let Some(mtu) = stats.path_mtu {
+        say!("    Path MTU: {} bytes (largest unfragmented payload: -s {})", mtu, max_payload(target, mtu));
+    }
+
+    if let Some(send) = stats.average_send().filter(|_| stats.show_receive_path) {
+        say!(
+            "    Send path: avg send={} (max {}), avg wait for the reply={} (over {} replies)",
+            output::rtt(send),
+            output::rtt(stats.max_send),
+            output::rtt(stats.average_rtt().unwrap_or_default().saturating_sub(send)),
+            stats.sends
+        );
+    }
+
+    if let Some(delivery) = stats.average_delivery().filter(|_| stats.show_receive_path) {
+        let local = delivery + stats.average_send().unwrap_or_default();
+        let network = stats.average_rtt().unwrap_or_default().saturating_sub(local);
+        say!(
+            "    Receive path: avg network={} + delivery={} (kernel receive to read, over {} replies)",
+            output::rtt(network),
+            output::rtt(delivery),
+            stats.deliveries
+        );
+    }
+
+    if stats.rx_drops > 0 {
+        say!("    Local drops: rx-drops={} (discarded by this host's kernel, not lost on the network)", stats.rx_drops);
+    }
+
+    if stats.crc_mismatches > 0 {
+        say!("    Corrupted: {} replies failed the payload CRC check", stats.crc_mismatches);
+    }
+
+    if stats.truncated > 0 {
+        say!("    Truncated: {} replies were larger than the receive buffer and cut short; raise --recv-buffer", stats.truncated);
+    }
+
+    if stats.short_replies > 0 {
+        say!(
+            "    Short replies: {} echoed less payload than was sent; a middlebox on the path may be truncating ICMP",
+            stats.short_replies
+        );
+    }
+
+    if stats.reordered > 0 {
+        say!("    Reordered: {} replies overtook an earlier probe", stats.reordered);
+    }
+
+    if stats.duplicates > 0 {
+        say!("    Duplicates: {} late replies to probes already counted as lost", stats.duplicates);
+    }
+
+    if stats.rtt_violations > 0 {
+        say!("    Slow replies: {} exceeded the RTT limit", stats.rtt_violations);
+    }
+
+    if stats.other_responders > 0 {
+        say!("    Other responders: {} replies came from an address other than the target", stats.other_responders);
+    }
+
+    if stats.ttl_warnings > 0 {
+        say!("    Low TTL: {} replies arrived below the --warn-on-ttl-below threshold", stats.ttl_warnings);
+    }
+
+    if let Some(ttl) = stats.reflectable_ttl.filter(|_| stats.ttl_reflected()) {
+        say!(
+            "    Reflected TTL: every reply carried TTL {}, the value we sent; the responder may be echoing our TTL, so it says nothing about the hop count",
+            ttl
+        );
+    }
+
+    if stats.retried > 0 {
+        say!(
+            "    Retries: {} probes needed a retry, {} answered after retrying",
+            stats.retried, stats.recovered
+        );
+    }
+
+    match (stats.average_rtt(), stats.rtt_range()) {
+        (Some(average), Some((min, max))) => {
+            say!("Approximate round trip times in {}:", output::unit_name());
+            say!("    Minimum = {}, Maximum = {}, Average = {}", output::rtt(min), output::rtt(max), output::rtt(average));
+        }
+        _ if stats.sent > 0 => say!("Round trip times: no RTT data, as no replies were received."),
+        _ => {}
+    }
+
+    if let (Some(trimmed), Some(pct)) = (stats.trimmed_mean_rtt(), stats.trim_percent) {
+        say!(
+            "    Trimmed mean = {} (dropping {}% at each end){}",
+            output::rtt(trimmed),
+            pct,
+            if stats.samples.is_sampled() { " (estimated from a sample)" } else { "" }
+        );
+    }
+
+    if let (Some(median), Some(p95)) = (stats.samples.percentile(50.0), stats.samples.percentile(95.0)) {
+        say!(
+            "    Median = {}, 95th percentile = {}{}",
+            output::rtt(median),
+            output::rtt(p95),
+            if stats.samples.is_sampled() { " (estimated from a sample)" } else { "" }
+        );
+    }
+}
+
+fn print_histogram(stats: &PingStatistics, options: &Options) {
+    let Some(bounds) = &options.histogram else {
+        return;
+    };
+    let lines = histogram::render(stats.samples.values(), bounds);
+    if lines.is_empty() {
+        return;
+    }
+
+    say!("RTT histogram{}:", if stats.samples.is_sampled() { " (estimated from a sample)" } else { "" });
+    for line in lines {
+        say!("{}", line);
+    }
+}
+
+fn run_compare(targets: [IpAddr; 2], options: &Options) {
+    let &Options { mut count, packet_size, .. } = options;
+
+    let mut sockets = Vec::with_capacity(targets.len());
+    for target in targets {
+        match create_socket(target, options) {
+            Ok(created) => sockets.push(created),
+            Err(e) => {
+                log_error!("failed to create socket for {}: {}", target, e);
+                report_error(json::error("socket", target, &e), format!("Failed to create socket for {}: {}", target, e));
+                return;
+            }
+        }
+    }
+
+    let identifier = base_identifier(options);
+    let recent_ids = VecDeque::from([identifier]);
+    let mut payload_rng = payload_rng(options);
+    let mut sequence = initial_sequence(options);
+    let mut rounds = 0;
+    let mut replies = [0u32; 2];
+    let mut totals = [Duration::ZERO; 2];
+
+    while count != Some(0) {
+        let round_started = Instant::now();
+        for (index, target) in targets.iter().enumerate() {
+            // Both targets share one identifier, so every probe gets its own
+            // sequence number to keep their replies apart on raw sockets.
+            sequence = next_sequence(sequence, options);
+            let packet = create_icmp_packet(packet_size, *target, identifier, sequence, payload_rng.as_mut(), options.payload_crc);
+            let dest_addr = SocketAddr::new(*target, 0);
+            let (socket, kind) = &sockets[index];
+
+            match send_and_receive_ring(socket, *kind, &packet, &dest_addr, &recent_ids, options) {
+                Ok(reply) => {
+                    replies[index] += 1;
+                    totals[index] += reply.rtt;
+                    say!("Reply from {}: bytes={} time={}", target, packet_size, output::rtt(reply.rtt));
+                }
+                Err(_) => say!("Request to {} timed out.", target),
+            }
+        }
+
+        rounds += 1;
+        count_down(&mut count);
+        if count != Some(0) {
+            sleep_until_next_probe(round_started, options.interval);
+        }
+    }
+
+    say!("\nring comparison:");
+    for (index, target) in targets.iter().enumerate() {
+        let average = totals[index].checked_div(replies[index]);
+        match average {
+            Some(average) => say!(
+                "    {}: Received = {}/{}, Average = {}",
+                target,
+                replies[index],
+                rounds,
+                output::rtt(average)
+            ),
+            None => say!("    {}: Received = 0/{}, no replies", target, rounds),
+        }
+    }
+
+    let (Some(first), Some(second)) = (totals[0].checked_div(replies[0]), totals[1].checked_div(replies[1])) else {
+        say!("Not enough replies to compare the targets.");
+        return;
+    };
+
+    let samples = replies[0].min(replies[1]);
+    let confidence = match samples {
+        0..=9 => "low confidence, fewer than 10 samples per target",
+        10..=29 => "moderate confidence, fewer than 30 samples per target",
+        _ => "high confidence",
+    };
+
+    if first == second {
+        say!("Both targets averaged {} ({})", output::rtt(first), confidence);
+    } else {
+        let (faster, delta) = if first < second {
+            (targets[0], second - first)
+        } else {
+            (targets[1], first - second)
+        };
+        say!("{} is faster by {} on average ({})", faster, output::rtt(delta), confidence);
+    }
+}
+
+/// Sends ICMP Address Mask Requests (RFC 950, type 17) and prints the subnet
+/// mask from each Address Mask Reply (type 18). Some legacy and embedded
+/// devices still answer these when echo is filtered.
+fn run_address_mask(target: IpAddr, options: &Options) {
+    if target.is_ipv6() {
+        report_error(
+            json::error("address_mask", target, "needs an IPv4 target"),
+            "Address mask requests only exist in ICMPv4.".to_string(),
+        );
+        return;
+    }
+
+    let (socket, kind) = match create_socket(target, options) {
+        Ok(created) => created,
+        Err(e) => {
+            report_error(json::error("socket", target, &e), format!("Failed to create socket: {}", e));
+            return;
+        }
+    };
+    if kind == SocketKind::Dgram {
+        report_error(
+            json::error("socket", target, "needs a raw socket"),
+            "Address mask requests need a raw socket; run ring with elevated privileges.".to_string(),
+        );
+        return;
+    }
+
+    let identifier = std::process::id() as u16;
+    let dest_addr = socket2::SockAddr::from(SocketAddr::new(target, 0));
+    let mut stats = PingStatistics::new();
+    let mut buffer = vec![0u8; options.recv_buffer];
+
+    let count = options.count.unwrap_or(1).max(1) as u16;
+    for sequence in 1..=count {
+        let packet = create_address_mask_request(identifier, sequence);
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(options.timeout as u64);
+        stats.sent += 1;
+
+        if let Err(e) = socket.send_to(&packet, &dest_addr) {
+            report_error(json::error("send", target, &e), format!("Failed to send address mask request: {}", e));
+            continue;
+        }
+        pcap::sent(&dest_addr, &packet);
+
+        let mut mask = None;
+        let mut truncated = false;
+        while let Ok(Some(received)) = receive_before(&socket, &mut buffer, deadline) {
+            let Some(icmp) = parse_received(&buffer[..received.len], kind, target) else {
+                continue;
+            };
+            if icmp.icmp_type != ICMP_ADDRESS_MASK_REPLY || (icmp.identifier, icmp.sequence) != (identifier, sequence) {
+                continue;
+            }
+            truncated = received.truncated;
+            if let Some(&[a, b, c, d]) = icmp.payload.get(..4) {
+                mask = Some(Ipv4Addr::new(a, b, c, d));
+                break;
+            }
+        }
+
+        if truncated {
+            stats.truncated += 1;
+        }
+        match mask {
+            Some(mask) => {
+                let rtt = start.elapsed();
+                stats.record_reply(rtt);
+                let line = format!("Address mask reply from {}: mask={} time={}", target, mask, output::rtt(rtt));
+                say!("{}", if truncated { highlight(&format!("{} [truncated]", line)) } else { line });
+            }
+            None if truncated => say!("{}", highlight("Address mask reply cut short before the mask [truncated]; raise --recv-buffer")),
+            None => say!("No address mask reply."),
+        }
+
+        if sequence < count {
+            sleep_until_next_probe(start, options.interval);
+        }
+    }
+
+    print_statistics(target, &stats);
+}
+
+const ICMP_ADDRESS_MASK_REQUEST: u8 = 17;
+const ICMP_ADDRESS_MASK_REPLY: u8 = 18;
+
+/// Builds a 12-byte Address Mask Request: the echo-style header followed by
+/// a zeroed 32-bit mask field the responder fills in.
+fn create_address_mask_request(identifier: u16, sequence: u16) -> Vec<u8> {
+    let mut packet = vec![0u8; ICMP_HEADER_LEN + 4];
+
+    packet[0] = ICMP_ADDRESS_MASK_REQUEST; // Type: Address Mask Request
+    packet[1] = 0; // Code: 0
+    Endian::current().put(&mut packet[4..6], identifier); // Identifier
+    Endian::current().put(&mut packet[6..8], sequence); // Sequence Number
+
+    let checksum = compute_checksum(&packet);
+    packet[2] = (checksum >> 8) as u8;
+    packet[3] = (checksum & 0xFF) as u8;
+
+    packet
+}
+
+fn print_socket_details(target: IpAddr, kind: SocketKind) {
+    let (family, protocol) = match target {
+        IpAddr::V4(_) => ("AF_INET", "ICMPv4"),
+        IpAddr::V6(_) => ("AF_INET6", "ICMPv6"),
+    };
+    let privileges = match has_elevated_privileges() {
+        Some(true) => "elevated",
+        Some(false) => "unprivileged",
+        None => "unknown",
+    };
+
+    say!(
+        "Using {} socket, protocol {}, family {} on {} (privileges: {})",
+        kind.name(),
+        protocol,
+        family,
+        env::consts::OS,
+        privileges
+    );
+}
+
+/// Shortest `-i` allowed without raw socket privileges, as in iputils ping.
+const MIN_UNPRIVILEGED_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Whether a raw ICMP socket can be opened, which is what separates a
+/// privileged run from the datagram fallback.
+fn can_open_raw_socket() -> bool {
+    Socket::new(Domain::IPV4, Type::from(SOCK_RAW), Some(Protocol::ICMPV4)).is_ok()
+        || Socket::new(Domain::IPV6, Type::from(SOCK_RAW), Some(Protocol::ICMPV6)).is_ok()
+}
+
+#[cfg(unix)]
+fn has_elevated_privileges() -> Option<bool> {
+    Some(unsafe { libc::geteuid() } == 0)
+}
+
+#[cfg(not(unix))]
+fn has_elevated_privileges() -> Option<bool> {
+    None
+}
+
+fn create_socket(target: IpAddr, options: &Options) -> io::Result<(Socket, SocketKind)> {
+    let &Options { ttl, timeout, .. } = options;
+
     let domain = match target {
         IpAddr::V4(_) => Domain::IPV4,
         IpAddr::V6(_) => Domain::IPV6,
@@ -137,43 +2807,108 @@ fn create_socket(target: IpAddr, ttl: i32, timeout: i32) -> io::Result<Socket> {
         IpAddr::V6(_) => Protocol::ICMPV6,
     };
 
-    let socket = Socket::new(domain, Type::from(SOCK_RAW), Some(protocol))?;
+    let (socket, kind) = match Socket::new(domain, Type::from(SOCK_RAW), Some(protocol)) {
+        Ok(socket) => (socket, SocketKind::Raw),
+        // Unprivileged users can still ping through ICMP datagram sockets where
+        // the kernel allows it (Linux ping_group_range, macOS).
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            (Socket::new(domain, Type::DGRAM, Some(protocol))?, SocketKind::Dgram)
+        }
+        Err(e) => return Err(e),
+    };
 
     socket.set_read_timeout(Some(Duration::from_millis(timeout as u64)))?;
     socket.set_write_timeout(Some(Duration::from_millis(timeout as u64)))?;
 
-    if let IpAddr::V6(_) = target {
-        socket.set_ttl(ttl as u32)?;
-    } else {
+    if target.is_ipv6() {
+        // socket2's set_ttl sets IP_TTL, which IPv6 sockets ignore or reject;
+        // the IPv6 equivalent is the unicast hop limit.
+        socket.set_unicast_hops_v6(ttl as u32)?;
+    } else if target.is_multicast() {
         socket.set_multicast_ttl_v4(ttl as u32)?;
+    } else {
+        socket.set_ttl(ttl as u32)?;
     }
 
-    Ok(socket)
-}
+    if let Some(tos) = options.tos {
+        match target {
+            IpAddr::V4(_) => socket.set_tos(tos as u32)?,
+            IpAddr::V6(_) => socket.set_tclass_v6(tos as u32)?,
+        }
+    }
 
-fn create_icmp_packet(payload_size: usize, target: IpAddr) -> Vec<u8> {
-    let mut packet = vec![0u8; 8 + payload_size];
+    if options.broadcast && target.is_ipv4() {
+        socket.set_broadcast(true)?;
+    }
 
-    match target {
-        IpAddr::V4(_) => {
-            packet[0] = 8; // ICMP Type: Echo Request (IPv4)
-            packet[1] = 0; // Code: 0
+    if let IpAddr::V6(ip) = target {
+        if ip.is_multicast() {
+            socket.set_multicast_hops_v6(ttl as u32)?;
         }
-        IpAddr::V6(_) => {
-            packet[0] = 128; // ICMPv6 Type: Echo Request
-            packet[1] = 0; // Code: 0
+    }
+
+    // Firewall and policy-routing rules can match on the mark to steer probes
+    // onto a specific link; setting it needs CAP_NET_ADMIN.
+    if let Some(mark) = options.mark {
+        #[cfg(target_os = "linux")]
+        socket
+            .set_mark(mark)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot set SO_MARK {}: {}", mark, e)))?;
+        #[cfg(not(target_os = "linux"))]
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("SO_MARK {} is only supported on Linux", mark)));
+    }
+
+    report_received_ttl(&socket, target, kind);
+    enable_error_queue(&socket, target);
+    report_receive_drops(&socket);
+    report_kernel_timestamps(&socket);
+
+    // Only raw IPv4 sockets hand us the IP header; everywhere else the
+    // TOS / traffic class has to be requested as ancillary data instead.
+    if options.tos_echo {
+        match target {
+            IpAddr::V4(_) if kind == SocketKind::Dgram => socket.set_recv_tos(true)?,
+            IpAddr::V4(_) => {}
+            IpAddr::V6(_) => socket.set_recv_tclass_v6(true)?,
         }
     }
 
+    Ok((socket, kind))
+}
+
+/// Random source for echo payloads: seeded from `--seed` so two runs send
+/// identical bytes, or from OS entropy otherwise. `--zero-payload` has no
+/// random source at all and leaves the payload zeroed.
+fn payload_rng(options: &Options) -> Option<StdRng> {
+    if options.zero_payload {
+        return None;
+    }
+    match options.seed {
+        Some(seed) => Some(StdRng::seed_from_u64(seed)),
+        None => Some(StdRng::from_entropy()),
+    }
+}
+
+fn create_icmp_packet(payload_size: usize, target: IpAddr, identifier: u16, sequence: u16, rng: Option<&mut StdRng>, crc: bool) -> Vec<u8> {
+    let mut packet = vec![0u8; ICMP_HEADER_LEN + payload_size];
+
+    packet[0] = echo_request_type(target); // Type: Echo Request
+    packet[1] = 0; // Code: 0
+
     packet[2] = 0; // Checksum (initially 0, will be calculated)
     packet[3] = 0;
-    packet[4] = 0; // Identifier
-    packet[5] = 1;
-    packet[6] = 0;
-    packet[7] = 1;
+    Endian::current().put(&mut packet[4..6], identifier); // Identifier
+    Endian::current().put(&mut packet[6..8], sequence); // Sequence Number
 
-    let mut rng = rand::thread_rng();
-    rng.fill(&mut packet[8..]);
+    // `-s 0` is valid and yields a header-only echo request; the checksum
+    // below then covers just the 8 header bytes. Without a random source the
+    // payload keeps its zeroes and the checksum is computed over them as usual.
+    if let Some(rng) = rng.filter(|_| payload_size > 0) {
+        rng.fill(&mut packet[ICMP_HEADER_LEN..]);
+    }
+    if crc {
+        packet::seal_payload(&mut packet[ICMP_HEADER_LEN..]);
+    }
 
     let checksum = compute_checksum(&packet);
     packet[2] = (checksum >> 8) as u8;
@@ -182,6 +2917,54 @@ fn create_icmp_packet(payload_size: usize, target: IpAddr) -> Vec<u8> {
     packet
 }
 
+/// Flips the checksum of an ICMP packet so that every receiver that checks
+/// it drops the packet (`--bad-checksum`).
+fn break_checksum(packet: &mut [u8]) {
+    packet[2] ^= 0xff;
+    packet[3] ^= 0xff;
+}
+
+/// Only raw IPv4 sockets send the ICMP checksum they are given; the kernel
+/// fills it in for ICMPv6 and for datagram sockets.
+fn corruptible_checksum(kind: SocketKind, target: IpAddr) -> io::Result<()> {
+    if target.is_ipv6() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "the kernel computes ICMPv6 checksums itself"));
+    }
+    if kind != SocketKind::Raw {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "a raw socket is needed; run ring as root"));
+    }
+    Ok(())
+}
+
+/// Switches a raw IPv4 socket to `IP_HDRINCL`, so the headers
+/// `spoofed_datagram` builds go out as they are.
+fn include_ip_header(socket: &Socket, kind: SocketKind, target: IpAddr) -> io::Result<()> {
+    if target.is_ipv6() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "only IPv4 targets are supported"));
+    }
+    if kind != SocketKind::Raw {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "a raw socket is needed; run ring as root"));
+    }
+    socket.set_header_included_v4(true)
+}
+
+/// Puts an IPv4 header claiming to come from `source` in front of `icmp`.
+/// With `IP_HDRINCL` the kernel no longer applies `-ttl` and `-Q`, so they
+/// are written here.
+fn spoofed_datagram(source: Ipv4Addr, target: Ipv4Addr, icmp: &[u8], options: &Options) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(IPV4_HEADER_LEN + icmp.len());
+    datagram.extend_from_slice(&[0x45, options.tos.unwrap_or(0)]);
+    datagram.extend_from_slice(&((IPV4_HEADER_LEN + icmp.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&rand::thread_rng().gen::<u16>().to_be_bytes());
+    datagram.extend_from_slice(&[0, 0, options.ttl.clamp(1, 255) as u8, ICMPV4_PROTOCOL, 0, 0]);
+    datagram.extend_from_slice(&source.octets());
+    datagram.extend_from_slice(&target.octets());
+    let checksum = compute_checksum(&datagram);
+    datagram[10..12].copy_from_slice(&checksum.to_be_bytes());
+    datagram.extend_from_slice(icmp);
+    datagram
+}
+
 fn compute_checksum(data: &[u8]) -> u16 {
     let mut sum = 0u32;
     let mut chunks = data.chunks_exact(2);
@@ -202,44 +2985,897 @@ fn compute_checksum(data: &[u8]) -> u16 {
     !(sum as u16)
 }
 
-fn send_and_receive_ring(socket: &Socket, packet: &[u8], dest_addr: &SocketAddr, _timeout: i32) -> io::Result<Duration> {
+fn send_and_receive_ring(
+    socket: &Socket,
+    kind: SocketKind,
+    packet: &[u8],
+    dest_addr: &SocketAddr,
+    recent_ids: &VecDeque<u16>,
+    options: &Options,
+) -> io::Result<Reply> {
+    let target = dest_addr.ip();
     let start = Instant::now();
+    let deadline = start + Duration::from_millis(options.timeout as u64);
+    let sockaddr = socket2::SockAddr::from(*dest_addr);
+    let send = match (options.spoof_source, target) {
+        (Some(source), IpAddr::V4(target)) => {
+            let datagram = spoofed_datagram(source, target, packet, options);
+            socket.send_to(&datagram, &sockaddr)?;
+            let send = start.elapsed();
+            pcap::sent(&sockaddr, &datagram);
+            send
+        }
+        _ => {
+            socket.send_to(packet, &sockaddr)?;
+            let send = start.elapsed();
+            pcap::sent(&sockaddr, packet);
+            send
+        }
+    };
+
+    let mut buffer = vec![0u8; options.recv_buffer];
+
+    loop {
+        let received = match receive_before(socket, &mut buffer, deadline) {
+            Ok(Some(received)) => received,
+            Ok(None) => return Err(io::ErrorKind::TimedOut.into()),
+            Err(e) => match queued_error_for(socket, kind, packet, target, e)? {
+                Some(error) => return Err(io::Error::other(error)),
+                None => continue,
+            },
+        };
+        let rtt = start.elapsed();
+
+        let Some(icmp) = parse_received(&buffer[..received.len], kind, target) else {
+            continue;
+        };
+
+        // Raw sockets also see the ICMP errors routers send back about our
+        // requests, which is all there is where the error queue is missing.
+        if kind == SocketKind::Raw {
+            if let Some(quoted) = parse_icmp_error(&icmp, Family::of(target), Endian::current()) {
+                if (quoted.identifier, quoted.sequence) == echo_header(packet) {
+                    let from = received.source.map_or(target, |source| source.ip());
+                    return Err(io::Error::other(ProbeError { error: quoted.error, from, mtu: quoted.mtu }));
+                }
+                continue;
+            }
+        }
+
+        if !is_reply_to(&icmp, packet, kind, target, options.loose_match) {
+            if icmp.icmp_type == echo_reply_type(target) && recent_ids.contains(&icmp.identifier) {
+                log_debug!("ignoring late reply from {} (id={}, seq={})", target, icmp.identifier, icmp.sequence);
+            }
+            continue;
+        }
+
+        return Ok(Reply {
+            // Loosely matched replies may carry a zeroed sequence; report the probe's.
+            sequence: echo_header(packet).1,
+            source: received.source.map_or(target, |source| source.ip()),
+            bytes: icmp.payload.len(),
+            rtt,
+            tos: icmp.tos.or(received.tos),
+            ttl: icmp.ttl.or(received.ttl),
+            // A cut-off payload cannot be verified, so it is not judged at all.
+            payload_crc: (options.payload_crc && !received.truncated)
+                .then(|| payload_crc_matches(icmp.payload, packet.len() - ICMP_HEADER_LEN)),
+            rx_drops: received.rx_drops,
+            truncated: received.truncated,
+            delivery: received.delivery,
+            send: Some(send),
+        });
+    }
+}
+
+/// Listens for `grace` after a run for replies to the probes in `lost`,
+/// which were already reported as timed out, and returns how many arrived.
+fn collect_late_replies(
+    socket: &Socket,
+    kind: SocketKind,
+    target: IpAddr,
+    identifiers: &VecDeque<u16>,
+    lost: &mut VecDeque<u16>,
+    grace: Duration,
+    options: &Options,
+) -> u32 {
+    if lost.is_empty() {
+        return 0;
+    }
+    log_debug!("waiting {}ms for late replies to {} lost probes", grace.as_millis(), lost.len());
+    let deadline = Instant::now() + grace;
+    let mut buffer = vec![0u8; options.recv_buffer];
+    let mut late = 0;
+    while !lost.is_empty() {
+        let received = match receive_before(socket, &mut buffer, deadline) {
+            Ok(Some(received)) => received,
+            Ok(None) => break,
+            Err(_) => {
+                // An ICMP error for an earlier probe; it was reported already.
+                read_error_queue(socket, target);
+                continue;
+            }
+        };
+        let Some(icmp) = parse_received(&buffer[..received.len], kind, target) else {
+            continue;
+        };
+        if icmp.icmp_type != echo_reply_type(target) || (kind == SocketKind::Raw && !identifiers.contains(&icmp.identifier)) {
+            continue;
+        }
+        if let Some(index) = lost.iter().position(|sequence| *sequence == icmp.sequence) {
+            lost.remove(index);
+            late += 1;
+            say!("Late reply from {} for seq={}, after its timeout", target, icmp.sequence);
+        }
+    }
+    late
+}
+
+/// The payload size `--min-reply-size` expects, when `reply` echoed less.
+/// Replies cut short by our own receive buffer are `truncated` instead.
+fn short_reply(reply: &Reply, options: &Options) -> Option<usize> {
+    let expected = options.min_reply_size?.min(options.packet_size);
+    (!reply.truncated && reply.bytes < expected).then_some(expected)
+}
+
+/// Checks the `--payload-crc` trailer of a reply carrying `sent` payload bytes.
+/// A reply cut short before it reached ring is not judged, since the
+/// trailer is missing rather than wrong.
+fn payload_crc_matches(payload: &[u8], sent: usize) -> bool {
+    if payload.len() < sent {
+        log_debug!("reply payload truncated to {} of {} bytes; skipping CRC check", payload.len(), sent);
+        return true;
+    }
+    packet::payload_intact(&payload[..sent])
+}
+
+/// Waits for the next datagram, returning `None` once `deadline` has passed.
+/// A read interrupted by a signal is retried with whatever time is left
+/// instead of being reported as a lost probe.
+fn receive_before(socket: &Socket, buffer: &mut [u8], deadline: Instant) -> io::Result<Option<ReceivedPacket>> {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        match receive_packet(socket, buffer) {
+            Ok(received) => {
+                pcap::received(received.source, &buffer[..received.len], received.ttl);
+                return Ok(Some(received));
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                log_debug!("receive interrupted by a signal, retrying");
+            }
+            Err(e) if is_timeout(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// An ICMP error that came back in place of an echo reply.
+#[derive(Debug)]
+struct ProbeError {
+    error: IcmpError,
+    from: IpAddr,
+    /// Next-hop MTU, when the error says the probe was too big.
+    mtu: Option<u32>,
+}
+
+impl ProbeError {
+    fn reason(&self) -> String {
+        match self.mtu {
+            Some(mtu) => format!("{} (next-hop MTU {})", self.error.describe(), mtu),
+            None => self.error.describe().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (reported by {})", self.reason(), self.from)
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+fn probe_error(error: &io::Error) -> Option<&ProbeError> {
+    error.get_ref()?.downcast_ref()
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Identifier and sequence number of an echo request or reply.
+fn echo_header(icmp: &[u8]) -> (u16, u16) {
+    (
+        Endian::current().read([icmp[4], icmp[5]]),
+        Endian::current().read([icmp[6], icmp[7]]),
+    )
+}
+
+/// Whether `icmp` is the echo reply to the request in `packet`. Datagram
+/// sockets rewrite the identifier and only deliver our own replies, so
+/// there only the sequence number is compared. With `loose` only the
+/// identifier has to match, for stacks that zero or mangle the sequence; a
+/// late reply to an earlier probe is then taken for the current one.
+fn is_reply_to(icmp: &ParsedReply, packet: &[u8], kind: SocketKind, target: IpAddr, loose: bool) -> bool {
+    let (sent_id, sent_seq) = echo_header(packet);
+
+    icmp.icmp_type == echo_reply_type(target)
+        && (loose || icmp.sequence == sent_seq)
+        && (kind == SocketKind::Dgram || icmp.identifier == sent_id)
+}
+
+/// Broadcast and multicast targets can be answered by many hosts, so those
+/// requests are answered by collecting everyone rather than the first reply.
+fn collects_responders(target: IpAddr, options: &Options) -> bool {
+    options.broadcast || target.is_multicast()
+}
+
+/// A host that answered a responder collection.
+struct Responder {
+    address: IpAddr,
+    /// Its fastest reply.
+    rtt: Duration,
+    /// One of its replies was longer than `--recv-buffer` and cut short.
+    truncated: bool,
+}
+
+/// What one responder collection brought back.
+struct Collection {
+    /// Every distinct host that answered, in order of arrival.
+    responders: Vec<Responder>,
+    /// The fastest reply to each request, `None` where none came.
+    answers: Vec<Option<Duration>>,
+}
+
+/// Sends the echo requests spread evenly over the timeout and keeps reading
+/// until the timeout after the last one expires.
+fn send_and_collect_responders(socket: &Socket, kind: SocketKind, packets: &[Vec<u8>], dest_addr: &SocketAddr, options: &Options) -> io::Result<Collection> {
+    let timeout = Duration::from_millis(options.timeout as u64);
+    let spacing = timeout / packets.len() as u32;
     let sockaddr = socket2::SockAddr::from(*dest_addr);
-    socket.send_to(packet, &sockaddr)?;
+    let start = Instant::now();
+    let mut sent_at = Vec::with_capacity(packets.len());
+
+    let mut responders: Vec<Responder> = Vec::new();
+    let mut answers = vec![None; packets.len()];
+    let mut buffer = vec![0u8; options.recv_buffer];
+
+    loop {
+        if sent_at.len() < packets.len() && start.elapsed() >= spacing * sent_at.len() as u32 {
+            socket.send_to(&packets[sent_at.len()], &sockaddr)?;
+            pcap::sent(&sockaddr, &packets[sent_at.len()]);
+            sent_at.push(Instant::now());
+            continue;
+        }
+        let deadline = if sent_at.len() < packets.len() {
+            start + spacing * sent_at.len() as u32
+        } else {
+            sent_at[sent_at.len() - 1] + timeout
+        };
+        let Some(received) = receive_before(socket, &mut buffer, deadline)? else {
+            if sent_at.len() < packets.len() {
+                continue;
+            }
+            break;
+        };
+        let arrived = Instant::now();
+
+        let Some(source) = received.source.map(|source| source.ip()) else {
+            continue;
+        };
+        let Some(icmp) = parse_received(&buffer[..received.len], kind, dest_addr.ip()) else {
+            continue;
+        };
+        let Some(request) = packets[..sent_at.len()]
+            .iter()
+            .position(|packet| is_reply_to(&icmp, packet, kind, dest_addr.ip(), false))
+        else {
+            continue;
+        };
+
+        let rtt = arrived - sent_at[request];
+        answers[request] = Some(answers[request].map_or(rtt, |fastest: Duration| fastest.min(rtt)));
+        match responders.iter_mut().find(|known| known.address == source) {
+            Some(known) => {
+                known.rtt = known.rtt.min(rtt);
+                known.truncated |= received.truncated;
+            }
+            None => responders.push(Responder { address: source, rtt, truncated: received.truncated }),
+        }
+    }
+
+    Ok(Collection { responders, answers })
+}
+
+// Echo message types differ between ICMPv4 (RFC 792) and ICMPv6 (RFC 4443),
+// so requests and replies are always looked up through the target's family.
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV4_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+fn echo_request_type(target: IpAddr) -> u8 {
+    match target {
+        IpAddr::V4(_) => ICMPV4_ECHO_REQUEST,
+        IpAddr::V6(_) => ICMPV6_ECHO_REQUEST,
+    }
+}
+
+fn echo_reply_type(target: IpAddr) -> u8 {
+    match target {
+        IpAddr::V4(_) => ICMPV4_ECHO_REPLY,
+        IpAddr::V6(_) => ICMPV6_ECHO_REPLY,
+    }
+}
+
+fn includes_ip_header(kind: SocketKind, target: IpAddr) -> bool {
+    kind == SocketKind::Raw && target.is_ipv4()
+}
 
-    let mut buffer = [std::mem::MaybeUninit::<u8>::uninit(); 1024];
-    let read_size = socket.recv(&mut buffer)?;
+/// Parses a datagram received on a socket of `kind` opened for `target`.
+fn parse_received(data: &[u8], kind: SocketKind, target: IpAddr) -> Option<ParsedReply<'_>> {
+    parse_icmp_reply(data, Family::of(target), includes_ip_header(kind, target), Endian::current())
+}
+
+/// Asks the kernel to attach the received TTL / hop limit as ancillary data
+/// where it is not already visible in a raw IPv4 header. Failure only means
+/// replies show the configured TTL instead, so it is not fatal.
+#[cfg(unix)]
+fn report_received_ttl(socket: &Socket, target: IpAddr, kind: SocketKind) {
+    let (level, option) = match target {
+        IpAddr::V4(_) if kind == SocketKind::Raw => return,
+        IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_RECVTTL),
+        IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT),
+    };
+
+    if let Err(e) = enable_socket_option(socket, level, option) {
+        log_debug!("cannot request received TTL for {}: {}", target, e);
+    }
+}
+
+#[cfg(not(unix))]
+fn report_received_ttl(_socket: &Socket, _target: IpAddr, _kind: SocketKind) {}
+
+/// Has Linux queue the ICMP errors caused by our own packets on the socket,
+/// where `read_error_queue` picks them up. Unlike parsing inbound ICMP this
+/// also works on datagram sockets and the kernel does the matching.
+#[cfg(target_os = "linux")]
+fn enable_error_queue(socket: &Socket, target: IpAddr) {
+    let (level, option) = match target {
+        IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_RECVERR),
+        IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVERR),
+    };
+    if let Err(e) = enable_socket_option(socket, level, option) {
+        log_debug!("cannot enable the socket error queue for {}: {}", target, e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enable_error_queue(_socket: &Socket, _target: IpAddr) {}
+
+/// Has Linux attach the socket's receive-queue drop counter to every read,
+/// so local drops can be told apart from network loss.
+#[cfg(target_os = "linux")]
+fn report_receive_drops(socket: &Socket) {
+    if let Err(e) = enable_socket_option(socket, libc::SOL_SOCKET, libc::SO_RXQ_OVFL) {
+        log_debug!("cannot enable SO_RXQ_OVFL: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn report_receive_drops(_socket: &Socket) {}
+
+/// Has Linux stamp every datagram with the time it was received, so the
+/// delay before ring reads it can be told apart from the network RTT.
+#[cfg(target_os = "linux")]
+fn report_kernel_timestamps(socket: &Socket) {
+    if let Err(e) = enable_socket_option(socket, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS) {
+        log_debug!("cannot enable SO_TIMESTAMPNS: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn report_kernel_timestamps(_socket: &Socket) {}
+
+#[cfg(unix)]
+fn enable_socket_option(socket: &Socket, level: libc::c_int, option: libc::c_int) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// An ICMP error the kernel matched to one of our echo requests.
+struct QueuedError {
+    error: IcmpError,
+    from: Option<IpAddr>,
+    identifier: u16,
+    sequence: u16,
+    mtu: Option<u32>,
+}
+
+/// Drains the socket error queue. On Linux a pending entry turns the next
+/// read into a failure, so this is called whenever a read fails.
+#[cfg(target_os = "linux")]
+fn read_error_queue(socket: &Socket, target: IpAddr) -> Vec<QueuedError> {
+    let mut errors = Vec::new();
+
+    loop {
+        let mut buffer = [0u8; 576];
+        let mut control = [0u64; 32];
+        let mut iov = libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        let read_size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+        if read_size < 0 {
+            return errors;
+        }
 
-    let _received_data = unsafe {
-        std::slice::from_raw_parts(buffer.as_ptr() as *const u8, read_size)
+        // The queued payload is the packet we sent, starting at its ICMP header.
+        let Some(header) = buffer[..read_size as usize].get(..ICMP_HEADER_LEN) else {
+            continue;
+        };
+        let (identifier, sequence) = echo_header(header);
+
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let level_and_type = ((*cmsg).cmsg_level, (*cmsg).cmsg_type);
+                if matches!(level_and_type, (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR)) {
+                    let extended = libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err;
+                    let info = std::ptr::read_unaligned(extended);
+
+                    if info.ee_origin == libc::SO_EE_ORIGIN_ICMP || info.ee_origin == libc::SO_EE_ORIGIN_ICMP6 {
+                        if let Some(error) = IcmpError::classify(Family::of(target), info.ee_type, info.ee_code) {
+                            // For a too-big error the kernel leaves the advertised MTU in ee_info.
+                            let mtu = (error == IcmpError::FragmentationNeeded && info.ee_info > 0).then_some(info.ee_info);
+                            let from = offender(extended);
+                            if let Some(from) = from {
+                                pcap::queued_error(from, target, info.ee_type, info.ee_code, mtu, &buffer[..read_size as usize]);
+                            }
+                            errors.push(QueuedError { error, from, identifier, sequence, mtu });
+                        }
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_error_queue(_socket: &Socket, _target: IpAddr) -> Vec<QueuedError> {
+    Vec::new()
+}
+
+/// Address of the router that sent the ICMP error, stored right after the
+/// extended error record.
+#[cfg(target_os = "linux")]
+unsafe fn offender(extended: *const libc::sock_extended_err) -> Option<IpAddr> {
+    let address = libc::SO_EE_OFFENDER(extended) as *const libc::sockaddr;
+    let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+    let len = match std::ptr::read_unaligned(address).sa_family as libc::c_int {
+        libc::AF_INET => std::mem::size_of::<libc::sockaddr_in>(),
+        libc::AF_INET6 => std::mem::size_of::<libc::sockaddr_in6>(),
+        _ => return None,
     };
+    std::ptr::copy_nonoverlapping(address as *const u8, &mut storage as *mut libc::sockaddr_storage as *mut u8, len);
+    sockaddr_to_socket_addr(&storage).map(|address| address.ip())
+}
+
+/// Finds the queued error, if any, that belongs to the request in `packet`.
+/// Returns `Ok(None)` when the read failed for an unrelated queued error.
+fn queued_error_for(socket: &Socket, kind: SocketKind, packet: &[u8], target: IpAddr, failure: io::Error) -> io::Result<Option<ProbeError>> {
+    let queued = read_error_queue(socket, target);
+    if queued.is_empty() {
+        return Err(failure);
+    }
 
-    Ok(start.elapsed())
+    let (sent_id, sent_seq) = echo_header(packet);
+    Ok(queued
+        .into_iter()
+        .find(|entry| entry.sequence == sent_seq && (kind == SocketKind::Dgram || entry.identifier == sent_id))
+        .map(|entry| ProbeError { error: entry.error, from: entry.from.unwrap_or(target), mtu: entry.mtu }))
 }
 
+#[cfg(unix)]
+fn receive_packet(socket: &Socket, buffer: &mut [u8]) -> io::Result<ReceivedPacket> {
+    let mut control = [0u64; 16];
+    let mut source: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut source as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
 
-fn resolve_target(target: &str) -> Result<IpAddr, String> {
-    match (target, 0).to_socket_addrs() {
-        Ok(iter) => {
-            let mut ipv4_addr = None;
-            let mut ipv6_addr = None;
+    let read_size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    // Kernel timestamps are wall-clock time, so the read is as well.
+    let read_at = SystemTime::now();
+    if read_size < 0 {
+        return Err(io::Error::last_os_error());
+    }
 
-            for addr in iter {
-                match addr.ip() {
-                    IpAddr::V4(ipv4) => ipv4_addr = Some(IpAddr::V4(ipv4)),
-                    IpAddr::V6(ipv6) => ipv6_addr = Some(IpAddr::V6(ipv6)),
+    let mut tos = None;
+    let mut ttl = None;
+    let mut rx_drops = None;
+    let mut delivery = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    let value = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                    tos = Some(value as u8);
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) | (libc::IPPROTO_IP, libc::IP_TTL) => {
+                    let value = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                    ttl = Some(value as u8);
+                }
+                (libc::IPPROTO_IP, libc::IP_TOS) => tos = Some(*libc::CMSG_DATA(cmsg)),
+                #[cfg(target_os = "linux")]
+                (libc::SOL_SOCKET, libc::SO_RXQ_OVFL) => {
+                    rx_drops = Some(std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const u32));
+                }
+                #[cfg(target_os = "linux")]
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                    let stamp = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timespec);
+                    let received = SystemTime::UNIX_EPOCH + Duration::new(stamp.tv_sec as u64, stamp.tv_nsec as u32);
+                    // A clock step between the two readings makes this meaningless.
+                    delivery = read_at.duration_since(received).ok();
                 }
+                _ => {}
             }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok(ReceivedPacket {
+        len: read_size as usize,
+        source: sockaddr_to_socket_addr(&source),
+        tos,
+        ttl,
+        rx_drops,
+        truncated: msg.msg_flags & libc::MSG_TRUNC != 0,
+        delivery,
+    })
+}
+
+#[cfg(unix)]
+fn sockaddr_to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddr::new(IpAddr::V4(ip), u16::from_be(addr.sin_port)))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Some(SocketAddr::new(IpAddr::V6(ip), u16::from_be(addr.sin6_port)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn receive_packet(socket: &Socket, buffer: &mut [u8]) -> io::Result<ReceivedPacket> {
+    let mut raw = vec![std::mem::MaybeUninit::<u8>::uninit(); buffer.len()];
+    let (read_size, source) = socket.recv_from(&mut raw)?;
+
+    let received_data = unsafe {
+        std::slice::from_raw_parts(raw.as_ptr() as *const u8, read_size)
+    };
+    buffer[..read_size].copy_from_slice(received_data);
+
+    Ok(ReceivedPacket {
+        len: read_size,
+        source: source.as_socket(),
+        tos: None,
+        ttl: None,
+        rx_drops: None,
+        // Without MSG_TRUNC a datagram that filled the buffer exactly may
+        // have gone on; assume it did.
+        truncated: read_size == buffer.len(),
+        delivery: None,
+    })
+}
+
+/// Resolves `target`, preferring IPv4 when it has addresses of both
+/// families. Without `index` the last address of that family is used;
+/// with it the addresses are listed and the one at `index` is picked.
+///
+/// The system resolver runs on a helper thread so that a stuck lookup cannot
+/// hold up the run for longer than `timeout`; the thread is abandoned on
+/// timeout. With `--resolver` the query goes to those servers instead.
+fn resolve_target(target: &str, timeout: Duration, index: Option<usize>) -> Result<IpAddr, String> {
+    if dns::has_resolvers() {
+        return resolve_with_resolvers(target, timeout, index);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let host = target.to_string();
+    thread::spawn(move || {
+        let _ = sender.send((host.as_str(), 0).to_socket_addrs());
+    });
+
+    let addresses = match receiver.recv_timeout(timeout) {
+        Ok(addresses) => addresses,
+        Err(_) => return Err(format!("Resolving {} timed out after {}ms", target, timeout.as_millis())),
+    };
+
+    let addresses = match addresses {
+        Ok(iter) => iter,
+        Err(e) => return Err(format!("Failed to resolve domain: {}", e)),
+    };
+
+    // The resolver repeats each address once per socket type.
+    let mut ipv4_addrs = Vec::new();
+    let mut ipv6_addrs = Vec::new();
+    for addr in addresses {
+        let family = if addr.is_ipv4() { &mut ipv4_addrs } else { &mut ipv6_addrs };
+        if !family.contains(&addr.ip()) {
+            family.push(addr.ip());
+        }
+    }
+    pick_address(target, ipv4_addrs, ipv6_addrs, index)
+}
+
+/// Resolves `target` through the `--resolver` servers and says which
+/// resolver and record the address came from.
+fn resolve_with_resolvers(target: &str, timeout: Duration, index: Option<usize>) -> Result<IpAddr, String> {
+    let answer = dns::resolve(target, timeout).map_err(|e| format!("Failed to resolve domain: {}", e))?;
+    let (ipv4_addrs, ipv6_addrs): (Vec<IpAddr>, Vec<IpAddr>) =
+        answer.records.iter().map(|record| record.address).partition(IpAddr::is_ipv4);
+    let address = pick_address(target, ipv4_addrs, ipv6_addrs, index)?;
+
+    let via = if answer.cnames.is_empty() { String::new() } else { format!(" (CNAME {})", answer.cnames.join(" -> ")) };
+    if let Some(record) = answer.records.iter().find(|record| record.address == address) {
+        say!("{} resolved via {}{}: {} {} (TTL {}s)", target, answer.resolver, via, record.kind(), address, record.ttl);
+    }
+    Ok(address)
+}
+
+/// Picks the address to ping, preferring IPv4 like `resolve_target`.
+fn pick_address(target: &str, ipv4_addrs: Vec<IpAddr>, ipv6_addrs: Vec<IpAddr>, index: Option<usize>) -> Result<IpAddr, String> {
+    let candidates = if ipv4_addrs.is_empty() { ipv6_addrs } else { ipv4_addrs };
+
+    let Some(index) = index else {
+        return candidates.last().copied().ok_or_else(|| "No valid IP address found.".to_string());
+    };
+    say!("{} resolved to {} address(es):", target, candidates.len());
+    for (position, address) in candidates.iter().enumerate() {
+        say!("    [{}] {}{}", position, address, if position == index { " (selected)" } else { "" });
+    }
+    candidates.get(index).copied().ok_or_else(|| {
+        format!("--address-index {} is out of range; {} has {} address(es)", index, target, candidates.len())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> (Command, Vec<String>) {
+        parse_command(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn a_bare_target_is_an_implicit_ping() {
+        let legacy = command(&["ring", "1.1.1.1", "-c", "3"]);
+        assert_eq!(legacy.0, Command::Ping);
+        assert_eq!(legacy, command(&["ring", "ping", "1.1.1.1", "-c", "3"]));
+    }
+
+    #[test]
+    fn sweeps_and_traces_are_commands_with_their_legacy_spellings() {
+        let sweep = command(&["ring", "sweep", "10.0.0.0/24"]);
+        assert_eq!(sweep, (Command::Sweep, vec!["ring".to_string(), "10.0.0.0/24".to_string()]));
+        assert_eq!(command(&["ring", "10.0.0.0/24"]), sweep);
+
+        assert_eq!(command(&["ring", "trace", "1.1.1.1"]).0, Command::Trace);
+        assert_eq!(command(&["ring", "1.1.1.1", "--path-monitor"]).0, Command::Trace);
+    }
+
+    #[test]
+    fn ipv4_mapped_targets_are_pinged_over_ipv4() {
+        let ip = lookup_target("::ffff:127.0.0.1", Duration::from_secs(1), None);
+        assert_eq!(ip, Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    }
+
+    #[test]
+    fn unspecified_targets_are_rejected() {
+        for target in ["::", "0.0.0.0", "::ffff:0.0.0.0"] {
+            assert!(lookup_target(target, Duration::from_secs(1), None).is_err(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn a_run_without_replies_has_no_rtt_data() {
+        let stats = PingStatistics { sent: 4, ..PingStatistics::new() };
+        assert_eq!(stats.lost(), 4);
+        assert_eq!(stats.loss_percent(), 100.0);
+        assert_eq!(stats.rtt_range(), None);
+        assert_eq!(stats.average_rtt(), None);
+        assert_eq!(stats.samples.percentile(50.0), None);
+    }
+
+    #[test]
+    fn a_single_reply_is_its_own_minimum_maximum_and_average() {
+        let rtt = Duration::from_millis(12);
+        let mut stats = PingStatistics { sent: 1, ..PingStatistics::new() };
+        stats.record_reply(rtt);
+        assert_eq!(stats.lost(), 0);
+        assert_eq!(stats.rtt_range(), Some((rtt, rtt)));
+        assert_eq!(stats.average_rtt(), Some(rtt));
+        assert_eq!(stats.samples.percentile(95.0), Some(rtt));
+    }
 
-            if let Some(ipv4) = ipv4_addr {
-                return Ok(ipv4);
+    const IDENTIFIER: u16 = 0x1234;
+    const SEQUENCE: u16 = 7;
+
+    /// The echo request to `target` and a reply to it of type `reply_type`.
+    fn echo_exchange(target: IpAddr, reply_type: u8) -> (Vec<u8>, Vec<u8>) {
+        let request = create_icmp_packet(8, target, IDENTIFIER, SEQUENCE, None, false);
+        let mut reply = request.clone();
+        reply[0] = reply_type;
+        (request, reply)
+    }
+
+    #[test]
+    fn echo_types_follow_the_address_family() {
+        let v4 = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        assert_eq!((echo_request_type(v4), echo_reply_type(v4)), (8, 0));
+        assert_eq!((echo_request_type(v6), echo_reply_type(v6)), (128, 129));
+        assert_eq!(create_icmp_packet(0, v4, IDENTIFIER, SEQUENCE, None, false)[0], 8);
+        assert_eq!(create_icmp_packet(0, v6, IDENTIFIER, SEQUENCE, None, false)[0], 128);
+    }
+
+    #[test]
+    fn a_zero_byte_payload_is_a_header_only_echo() {
+        for (target, reply_type) in [
+            (IpAddr::V4(Ipv4Addr::LOCALHOST), ICMPV4_ECHO_REPLY),
+            (IpAddr::V6(Ipv6Addr::LOCALHOST), ICMPV6_ECHO_REPLY),
+        ] {
+            let mut rng = StdRng::seed_from_u64(117);
+            let request = create_icmp_packet(0, target, IDENTIFIER, SEQUENCE, Some(&mut rng), false);
+            assert_eq!(request.len(), ICMP_HEADER_LEN);
+            // The kernel fills in the ICMPv6 checksum over a pseudo-header.
+            if target.is_ipv4() {
+                assert_eq!(compute_checksum(&request), 0);
             }
-            if let Some(ipv6) = ipv6_addr {
-                return Ok(ipv6);
+
+            let mut reply = request.clone();
+            reply[0] = reply_type;
+            let parsed = parse_received(&reply, SocketKind::Dgram, target).unwrap();
+            assert!(parsed.payload.is_empty());
+            assert!(is_reply_to(&parsed, &request, SocketKind::Raw, target, false), "{}", target);
+        }
+    }
+
+    #[test]
+    fn replies_match_only_with_their_familys_echo_reply_type() {
+        for (target, own, other) in [
+            (IpAddr::V4(Ipv4Addr::LOCALHOST), ICMPV4_ECHO_REPLY, ICMPV6_ECHO_REPLY),
+            (IpAddr::V6(Ipv6Addr::LOCALHOST), ICMPV6_ECHO_REPLY, ICMPV4_ECHO_REPLY),
+        ] {
+            for (reply_type, expected) in [(own, true), (other, false)] {
+                let (request, reply) = echo_exchange(target, reply_type);
+                let parsed = parse_received(&reply, SocketKind::Dgram, target).unwrap();
+                let matched = is_reply_to(&parsed, &request, SocketKind::Raw, target, false);
+                assert_eq!(matched, expected, "{} type {}", target, reply_type);
             }
+        }
+    }
+
+    #[test]
+    fn replies_larger_than_the_buffer_are_flagged_truncated() {
+        let target = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let (_, reply) = echo_exchange(target, ICMPV4_ECHO_REPLY);
+        let mut oversized = create_icmp_packet(200, target, IDENTIFIER, SEQUENCE, None, true);
+        oversized[0] = ICMPV4_ECHO_REPLY;
+
+        // A loopback UDP socket stands in for a datagram ICMP socket: both
+        // hand over the ICMP message without an IP header.
+        let receiver = Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        receiver.bind(&SocketAddr::new(target, 0).into()).unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let sender = Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        let address = receiver.local_addr().unwrap();
+        let mut buffer = [0u8; 64];
+
+        sender.send_to(&reply, &address).unwrap();
+        let received = receive_packet(&receiver, &mut buffer).unwrap();
+        assert_eq!(received.len, reply.len());
+        assert!(!received.truncated);
+
+        sender.send_to(&oversized, &address).unwrap();
+        let received = receive_packet(&receiver, &mut buffer).unwrap();
+        assert_eq!(received.len, buffer.len());
+        assert!(received.truncated);
+
+        // The header survives, so the reply still matches; the payload is cut
+        // short and its CRC is not judged.
+        let parsed = parse_received(&buffer[..received.len], SocketKind::Dgram, target).unwrap();
+        assert_eq!((parsed.identifier, parsed.sequence), (IDENTIFIER, SEQUENCE));
+        assert_eq!(parsed.payload.len(), buffer.len() - 8);
+        assert!(payload_crc_matches(parsed.payload, 200));
+    }
+
+    #[cfg(unix)]
+    extern "C" fn ignore_signal(_signal: libc::c_int) {}
 
-            Err("No valid IP address found.".to_string())
+    #[test]
+    #[cfg(unix)]
+    fn a_read_interrupted_by_a_signal_keeps_waiting_for_the_reply() {
+        let target = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let (_, reply) = echo_exchange(target, ICMPV4_ECHO_REPLY);
+        let receiver = Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        receiver.bind(&SocketAddr::new(target, 0).into()).unwrap();
+        let address = receiver.local_addr().unwrap();
+
+        // A read with a timeout fails with EINTR when a handled signal
+        // arrives, even under SA_RESTART. The reply only follows the signal.
+        unsafe {
+            libc::signal(libc::SIGUSR1, ignore_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
         }
-        Err(e) => Err(format!("Failed to resolve domain: {}", e)),
+        let reader = unsafe { libc::pthread_self() };
+        let signaller = thread::spawn(move || {
+            sleep(Duration::from_millis(100));
+            unsafe { libc::pthread_kill(reader, libc::SIGUSR1) };
+            sleep(Duration::from_millis(100));
+            Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap().send_to(&reply, &address).unwrap();
+        });
+
+        let mut buffer = [0u8; 64];
+        let received = receive_before(&receiver, &mut buffer, Instant::now() + Duration::from_secs(2)).unwrap();
+        signaller.join().unwrap();
+        assert_eq!(received.map(|received| received.len), Some(16));
+    }
+
+    #[test]
+    fn the_sleep_between_probes_leaves_out_the_rtt() {
+        let interval = Duration::from_millis(300);
+        let rtt = Duration::from_millis(200);
+
+        let started = Instant::now() - rtt;
+        sleep_until_next_probe(started, interval);
+        let period = started.elapsed();
+        assert!(period >= interval && period < interval + rtt / 2, "period {:?}", period);
+
+        // A probe that outlasts the interval is followed by the next at once.
+        let started = Instant::now() - interval * 2;
+        let before = Instant::now();
+        sleep_until_next_probe(started, interval);
+        assert!(before.elapsed() < rtt / 2);
     }
 }
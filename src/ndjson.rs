@@ -0,0 +1,101 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::json::Object;
+
+/// Rotated files kept beside the active one; `path.1` is the most recent.
+const MAX_ROTATED_FILES: u32 = 9;
+
+struct Output {
+    path: String,
+    file: File,
+    written: u64,
+    rotate_size: Option<u64>,
+}
+
+static OUTPUT: OnceLock<Mutex<Output>> = OnceLock::new();
+
+/// How `--json` and `--json-pretty` print records on stdout.
+#[derive(Clone, Copy)]
+pub enum Style {
+    Line,
+    Pretty,
+}
+
+static STDOUT: OnceLock<Style> = OnceLock::new();
+
+/// Prints every record on stdout as well, in `style`.
+pub fn to_stdout(style: Style) {
+    let _ = STDOUT.set(style);
+}
+
+/// Whether `--json` or `--json-pretty` put records on stdout in place of text.
+pub fn on_stdout() -> bool {
+    STDOUT.get().is_some()
+}
+
+/// Opens `path` for appending records. With `rotate_size`, a record that
+/// would push the file past that many bytes first shifts it to `path.1`,
+/// `path.1` to `path.2` and so on.
+pub fn open(path: &str, rotate_size: Option<u64>) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let written = file.metadata()?.len();
+    let _ = OUTPUT.set(Mutex::new(Output { path: path.to_string(), file, written, rotate_size }));
+    Ok(())
+}
+
+/// Emits one record to stdout and/or the `--ndjson-out` file, whichever are
+/// active. File records are flushed one by one.
+pub fn write(record: &Object) {
+    match STDOUT.get() {
+        Some(Style::Line) => println!("{}", record.to_line()),
+        Some(Style::Pretty) => println!("{}", record.to_pretty()),
+        None => {}
+    }
+
+    let Some(output) = OUTPUT.get() else {
+        return;
+    };
+    let Ok(mut output) = output.lock() else {
+        return;
+    };
+
+    let mut line = record.to_line();
+    line.push('\n');
+
+    if let Some(limit) = output.rotate_size {
+        if output.written > 0 && output.written + line.len() as u64 > limit {
+            if let Err(e) = output.rotate() {
+                log_warn!("failed to rotate {}: {}", output.path, e);
+            }
+        }
+    }
+
+    match output.file.write_all(line.as_bytes()).and_then(|_| output.file.flush()) {
+        Ok(()) => output.written += line.len() as u64,
+        Err(e) => {
+            static REPORTED: OnceLock<()> = OnceLock::new();
+            if REPORTED.set(()).is_ok() {
+                log_warn!("writing to {} failed: {}", output.path, e);
+            }
+        }
+    }
+}
+
+impl Output {
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..MAX_ROTATED_FILES).rev() {
+            let from = format!("{}.{}", self.path, index);
+            if Path::new(&from).exists() {
+                fs::rename(&from, format!("{}.{}", self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, format!("{}.1", self.path))?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::time::{Duration, Instant};
+
+use crate::packet::{parse_icmp_reply, Endian, Family};
+use crate::router::{interface_index, ND_HOP_LIMIT};
+use crate::{
+    compute_checksum, count_down, create_socket, json, output, pcap, print_statistics, receive_before, report_error,
+    sleep_until_next_probe, Options, PingStatistics, SocketKind,
+};
+
+const NEIGHBOR_SOLICITATION: u8 = 135;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+const OPTION_SOURCE_LINK_LAYER: u8 = 1;
+const OPTION_TARGET_LINK_LAYER: u8 = 2;
+const FLAG_ROUTER: u8 = 0x80;
+const FLAG_SOLICITED: u8 = 0x40;
+
+/// A Neighbor Advertisement for the address being probed.
+struct Advertisement {
+    router: bool,
+    solicited: bool,
+    link_layer: Option<String>,
+}
+
+/// Asks whether `target` is present on the link behind `interface` by
+/// sending Neighbor Solicitations to its solicited-node multicast group,
+/// `-c` times, and counting the Neighbor Advertisements that come back.
+///
+/// This checks layer-3 presence directly, so it still works where echo
+/// requests are filtered.
+pub fn run_neighbor_solicitation(target: Ipv6Addr, interface: &str, options: &Options) {
+    let Some(index) = interface_index(interface) else {
+        report_error(
+            json::error("interface", interface, "unknown network interface"),
+            format!("Unknown network interface: {}", interface),
+        );
+        return;
+    };
+
+    let group = solicited_node(target);
+    let (socket, kind) = match create_socket(IpAddr::V6(group), options) {
+        Ok(created) => created,
+        Err(e) => {
+            report_error(json::error("socket", target, &e), format!("Failed to create socket: {}", e));
+            return;
+        }
+    };
+    if kind == SocketKind::Dgram {
+        report_error(
+            json::error("socket", target, "needs a raw socket"),
+            "Neighbor solicitation needs a raw socket; run ring with elevated privileges.".to_string(),
+        );
+        return;
+    }
+    if let Err(e) = socket.set_multicast_hops_v6(ND_HOP_LIMIT).and_then(|_| socket.set_multicast_if_v6(index)) {
+        report_error(
+            json::error("socket", interface, &e),
+            format!("Failed to prepare {} for neighbor solicitation: {}", interface, e),
+        );
+        return;
+    }
+
+    let packet = build_solicitation(target, link_layer_address(interface));
+    let dest_addr = socket2::SockAddr::from(SocketAddr::V6(SocketAddrV6::new(group, 0, 0, index)));
+    let timeout = Duration::from_millis(options.timeout as u64);
+    let mut stats = PingStatistics::for_run(options);
+    let mut buffer = [0u8; 1500];
+    let mut remaining = options.count;
+
+    while remaining != Some(0) {
+        let start = Instant::now();
+        if let Err(e) = socket.send_to(&packet, &dest_addr) {
+            report_error(json::error("send", target, &e), format!("Failed to send neighbor solicitation: {}", e));
+            break;
+        }
+        pcap::sent(&dest_addr, &packet);
+        stats.sent += 1;
+
+        match await_advertisement(&socket, &mut buffer, target, start + timeout) {
+            Some(advertisement) => {
+                let rtt = start.elapsed();
+                stats.record_reply(rtt);
+                let mut line = format!("Neighbor {} is present on {}: time={}", target, interface, output::rtt(rtt));
+                if let Some(link_layer) = &advertisement.link_layer {
+                    line.push_str(&format!(" lladdr={}", link_layer));
+                }
+                if advertisement.router {
+                    line.push_str(" router");
+                }
+                if !advertisement.solicited {
+                    line.push_str(" (unsolicited)");
+                }
+                say!("{}", line);
+            }
+            None => say!("No neighbor advertisement from {}.", target),
+        }
+
+        count_down(&mut remaining);
+        if remaining != Some(0) {
+            sleep_until_next_probe(start, options.interval);
+        }
+    }
+
+    print_statistics(IpAddr::V6(target), &stats);
+}
+
+/// The `ff02::1:ffXX:XXXX` group every node joins for the addresses it owns.
+fn solicited_node(target: Ipv6Addr) -> Ipv6Addr {
+    let octets = target.octets();
+    Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | octets[13] as u16,
+        u16::from_be_bytes([octets[14], octets[15]]),
+    )
+}
+
+fn build_solicitation(target: Ipv6Addr, source_link_layer: Option<[u8; 6]>) -> Vec<u8> {
+    let mut packet = vec![NEIGHBOR_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&target.octets());
+    // Receivers only answer a multicast solicitation when they can learn
+    // where to send the advertisement (RFC 4861 section 7.1.1).
+    if let Some(mac) = source_link_layer {
+        packet.extend_from_slice(&[OPTION_SOURCE_LINK_LAYER, 1]);
+        packet.extend_from_slice(&mac);
+    }
+    let checksum = compute_checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
+fn await_advertisement(socket: &socket2::Socket, buffer: &mut [u8], target: Ipv6Addr, deadline: Instant) -> Option<Advertisement> {
+    while let Ok(Some(received)) = receive_before(socket, buffer, deadline) {
+        let Some(icmp) = parse_icmp_reply(&buffer[..received.len], Family::V6, false, Endian::current()) else {
+            continue;
+        };
+        if icmp.icmp_type != NEIGHBOR_ADVERTISEMENT || icmp.code != 0 || received.ttl.is_some_and(|ttl| ttl != 255) {
+            continue;
+        }
+        // The R/S/O flags open the rest of the header; the advertised
+        // address opens the payload.
+        let Some(advertised) = icmp.payload.get(..16) else {
+            continue;
+        };
+        if advertised != target.octets() {
+            continue;
+        }
+        let flags = icmp.rest_of_header[0];
+        return Some(Advertisement {
+            router: flags & FLAG_ROUTER != 0,
+            solicited: flags & FLAG_SOLICITED != 0,
+            link_layer: target_link_layer(&icmp.payload[16..]),
+        });
+    }
+    None
+}
+
+/// Formats the Target Link-Layer Address option, if the advertisement has one.
+fn target_link_layer(mut options: &[u8]) -> Option<String> {
+    while options.len() >= 2 {
+        let length = options[1] as usize * 8;
+        if length == 0 {
+            return None;
+        }
+        let option = options.get(..length)?;
+        if option[0] == OPTION_TARGET_LINK_LAYER && length >= 8 {
+            let mac: Vec<String> = option[2..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+            return Some(mac.join(":"));
+        }
+        options = &options[length..];
+    }
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn link_layer_address(interface: &str) -> Option<[u8; 6]> {
+    let text = std::fs::read_to_string(format!("/sys/class/net/{}/address", interface)).ok()?;
+    let mut mac = [0u8; 6];
+    let mut parts = text.trim().split(':');
+    for byte in &mut mac {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn link_layer_address(_interface: &str) -> Option<[u8; 6]> {
+    None
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::Duration;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static UNIT: AtomicU8 = AtomicU8::new(Unit::Millis as u8);
+
+/// The unit RTTs are printed in (`--unit`).
+#[derive(Clone, Copy, PartialEq)]
+pub enum Unit {
+    Millis,
+    Micros,
+    Seconds,
+}
+
+impl Unit {
+    /// Parses `ms`, `us` or `s`.
+    pub fn parse(value: &str) -> Option<Unit> {
+        match value {
+            "ms" => Some(Unit::Millis),
+            "us" => Some(Unit::Micros),
+            "s" => Some(Unit::Seconds),
+            _ => None,
+        }
+    }
+}
+
+/// Silences the regular per-reply and summary output printed with `say!`.
+/// Errors still go through `println!` so they are never hidden.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn set_unit(unit: Unit) {
+    UNIT.store(unit as u8, Ordering::Relaxed);
+}
+
+fn unit() -> Unit {
+    match UNIT.load(Ordering::Relaxed) {
+        value if value == Unit::Micros as u8 => Unit::Micros,
+        value if value == Unit::Seconds as u8 => Unit::Seconds,
+        _ => Unit::Millis,
+    }
+}
+
+/// An RTT in the `--unit` unit, without the suffix. Milliseconds and
+/// microseconds are whole numbers; seconds keep millisecond resolution.
+pub fn rtt_value(rtt: Duration) -> String {
+    match unit() {
+        Unit::Millis => rtt.as_millis().to_string(),
+        Unit::Micros => rtt.as_micros().to_string(),
+        Unit::Seconds => format!("{:.3}", rtt.as_secs_f64()),
+    }
+}
+
+/// An RTT in the `--unit` unit with its suffix, such as `12ms` or `850us`.
+pub fn rtt(rtt: Duration) -> String {
+    format!("{}{}", rtt_value(rtt), unit_suffix())
+}
+
+/// A signed RTT difference in the `--unit` unit with its suffix, such as
+/// `+1.2ms`. Differences are small, so this keeps one digit more than
+/// `rtt_value`.
+pub fn rtt_delta(seconds: f64) -> String {
+    match unit() {
+        Unit::Millis => format!("{:+.1}ms", seconds * 1000.0),
+        Unit::Micros => format!("{:+.0}us", seconds * 1_000_000.0),
+        Unit::Seconds => format!("{:+.4}s", seconds),
+    }
+}
+
+pub fn unit_suffix() -> &'static str {
+    match unit() {
+        Unit::Millis => "ms",
+        Unit::Micros => "us",
+        Unit::Seconds => "s",
+    }
+}
+
+/// The unit spelled out, for the summary heading.
+pub fn unit_name() -> &'static str {
+    match unit() {
+        Unit::Millis => "milli-seconds",
+        Unit::Micros => "micro-seconds",
+        Unit::Seconds => "seconds",
+    }
+}
+
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
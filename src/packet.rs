@@ -0,0 +1,365 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const ICMP_HEADER_LEN: usize = 8;
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPPROTO_ICMP: u8 = 1;
+const IPV6_HEADER_LEN: usize = 40;
+/// Size of the CRC32 trailer `--payload-crc` puts at the end of the payload.
+pub const CRC_LEN: usize = 4;
+
+/// Whether identifiers and sequence numbers go little-endian (`--id-endian`).
+static LITTLE_ENDIAN_FIELDS: AtomicBool = AtomicBool::new(false);
+
+/// Byte order of the echo identifier and sequence fields. RFC 792 makes
+/// them big-endian; `Little` exists only to talk to responders that
+/// byte-swap them, and is applied to both what is sent and what is matched.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+impl Endian {
+    /// Parses `big` or `little`.
+    pub fn parse(value: &str) -> Option<Endian> {
+        match value {
+            "big" => Some(Endian::Big),
+            "little" => Some(Endian::Little),
+            _ => None,
+        }
+    }
+
+    /// The byte order `--id-endian` selected.
+    pub fn current() -> Endian {
+        if LITTLE_ENDIAN_FIELDS.load(Ordering::Relaxed) {
+            Endian::Little
+        } else {
+            Endian::Big
+        }
+    }
+
+    /// Writes an identifier or sequence number in this byte order.
+    pub fn put(self, field: &mut [u8], value: u16) {
+        let bytes = match self {
+            Endian::Big => value.to_be_bytes(),
+            Endian::Little => value.to_le_bytes(),
+        };
+        field.copy_from_slice(&bytes);
+    }
+
+    /// Reads an identifier or sequence number written by `put`.
+    pub fn read(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endian::Big => u16::from_be_bytes(bytes),
+            Endian::Little => u16::from_le_bytes(bytes),
+        }
+    }
+}
+
+pub fn set_field_endian(endian: Endian) {
+    LITTLE_ENDIAN_FIELDS.store(endian == Endian::Little, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    pub fn of(address: IpAddr) -> Family {
+        match address {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+}
+
+/// The fields of a received ICMP message that ring looks at.
+pub struct ParsedReply<'a> {
+    pub icmp_type: u8,
+    pub code: u8,
+    /// Echo identifier and sequence number, read in the byte order the
+    /// parser was given.
+    pub identifier: u16,
+    pub sequence: u16,
+    /// Bytes 4 to 7 of the header as sent. Messages other than echoes keep
+    /// big-endian fields there (an MTU, a lifetime, flags), which
+    /// `--id-endian` must not swap.
+    pub rest_of_header: [u8; 4],
+    /// Everything after the 8-byte ICMP header.
+    pub payload: &'a [u8],
+    /// TOS byte of the IPv4 header, when the socket delivered one.
+    pub tos: Option<u8>,
+    /// TTL of the IPv4 header, when the socket delivered one.
+    pub ttl: Option<u8>,
+}
+
+/// Parses a datagram read from an ICMP socket. `ip_header` says whether the
+/// socket prepends the IPv4 header, which only raw IPv4 sockets do, and
+/// `endian` is the byte order of the echo fields (`Endian::current()`).
+///
+/// The bytes come straight off the network, so every length is checked and
+/// anything truncated or inconsistent yields `None` instead of a panic.
+pub fn parse_icmp_reply(data: &[u8], family: Family, ip_header: bool, endian: Endian) -> Option<ParsedReply<'_>> {
+    let (icmp, tos, ttl) = if ip_header {
+        if family != Family::V4 {
+            return None;
+        }
+        let (header_len, tos, ttl) = parse_ipv4_header(data)?;
+        (data.get(header_len..)?, Some(tos), Some(ttl))
+    } else {
+        (data, None, None)
+    };
+
+    let header = icmp.get(..ICMP_HEADER_LEN)?;
+    Some(ParsedReply {
+        icmp_type: header[0],
+        code: header[1],
+        identifier: endian.read([header[4], header[5]]),
+        sequence: endian.read([header[6], header[7]]),
+        rest_of_header: [header[4], header[5], header[6], header[7]],
+        payload: icmp.get(ICMP_HEADER_LEN..)?,
+        tos,
+        ttl,
+    })
+}
+
+/// Validates an IPv4 header carrying ICMP and returns its length, TOS and TTL.
+fn parse_ipv4_header(data: &[u8]) -> Option<(usize, u8, u8)> {
+    let first = *data.first()?;
+    if first >> 4 != 4 {
+        return None;
+    }
+
+    let header_len = ((first & 0x0F) as usize) * 4;
+    if header_len < IPV4_MIN_HEADER_LEN || data.len() < header_len {
+        return None;
+    }
+    if data[9] != IPPROTO_ICMP {
+        return None;
+    }
+    Some((header_len, data[1], data[8]))
+}
+
+/// Why an ICMP error said a probe could not be delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IcmpError {
+    NetUnreachable,
+    HostUnreachable,
+    ProtocolUnreachable,
+    PortUnreachable,
+    FragmentationNeeded,
+    AdminProhibited,
+    TtlExceeded,
+    Unreachable,
+}
+
+impl IcmpError {
+    pub fn describe(self) -> &'static str {
+        match self {
+            IcmpError::NetUnreachable => "Destination net unreachable",
+            IcmpError::HostUnreachable => "Destination host unreachable",
+            IcmpError::ProtocolUnreachable => "Destination protocol unreachable",
+            IcmpError::PortUnreachable => "Destination port unreachable",
+            IcmpError::FragmentationNeeded => "Packet too big",
+            IcmpError::AdminProhibited => "Administratively prohibited",
+            IcmpError::TtlExceeded => "TTL expired in transit",
+            IcmpError::Unreachable => "Destination unreachable",
+        }
+    }
+
+    pub fn classify(family: Family, icmp_type: u8, code: u8) -> Option<IcmpError> {
+        let error = match (family, icmp_type, code) {
+            // RFC 792 / RFC 1812.
+            (Family::V4, 3, 0 | 6 | 11) => IcmpError::NetUnreachable,
+            (Family::V4, 3, 1 | 7 | 12) => IcmpError::HostUnreachable,
+            (Family::V4, 3, 2) => IcmpError::ProtocolUnreachable,
+            (Family::V4, 3, 3) => IcmpError::PortUnreachable,
+            (Family::V4, 3, 4) => IcmpError::FragmentationNeeded,
+            (Family::V4, 3, 9 | 10 | 13) => IcmpError::AdminProhibited,
+            (Family::V4, 3, _) => IcmpError::Unreachable,
+            (Family::V4, 11, _) => IcmpError::TtlExceeded,
+            // RFC 4443.
+            (Family::V6, 1, 0) => IcmpError::NetUnreachable,
+            (Family::V6, 1, 1 | 5 | 6) => IcmpError::AdminProhibited,
+            (Family::V6, 1, 3) => IcmpError::HostUnreachable,
+            (Family::V6, 1, 4) => IcmpError::PortUnreachable,
+            (Family::V6, 1, _) => IcmpError::Unreachable,
+            (Family::V6, 2, _) => IcmpError::FragmentationNeeded,
+            (Family::V6, 3, _) => IcmpError::TtlExceeded,
+            _ => return None,
+        };
+        Some(error)
+    }
+}
+
+/// An ICMP error together with the echo request it quotes.
+pub struct QuotedError {
+    pub error: IcmpError,
+    pub identifier: u16,
+    pub sequence: u16,
+    /// Next-hop MTU advertised by a fragmentation-needed / packet-too-big error.
+    pub mtu: Option<u32>,
+}
+
+/// Interprets `reply` as an ICMP error about one of our echo requests and
+/// pulls the identifier and sequence number, in the `endian` byte order,
+/// out of the quoted packet.
+pub fn parse_icmp_error(reply: &ParsedReply, family: Family, endian: Endian) -> Option<QuotedError> {
+    let error = IcmpError::classify(family, reply.icmp_type, reply.code)?;
+
+    // After the 8-byte error header (whose last word is unused or an MTU)
+    // comes the start of the offending packet: its IP header and at least
+    // the first 8 bytes of our echo request.
+    let quoted = reply.payload;
+    let quoted_icmp = match family {
+        Family::V4 => {
+            let (header_len, _, _) = parse_ipv4_header(quoted)?;
+            quoted.get(header_len..)?
+        }
+        Family::V6 => quoted.get(IPV6_HEADER_LEN..)?,
+    };
+
+    let header = quoted_icmp.get(..ICMP_HEADER_LEN)?;
+    Some(QuotedError {
+        error,
+        identifier: endian.read([header[4], header[5]]),
+        sequence: endian.read([header[6], header[7]]),
+        mtu: next_hop_mtu(reply, family, error),
+    })
+}
+
+/// The MTU a router reported in the unused word of the error header: the low
+/// 16 bits for ICMPv4 (RFC 1191), all 32 for ICMPv6 (RFC 4443). Routers that
+/// predate RFC 1191 send zero.
+fn next_hop_mtu(reply: &ParsedReply, family: Family, error: IcmpError) -> Option<u32> {
+    if error != IcmpError::FragmentationNeeded {
+        return None;
+    }
+    let rest = reply.rest_of_header;
+    let mtu = match family {
+        Family::V4 => u16::from_be_bytes([rest[2], rest[3]]) as u32,
+        Family::V6 => u32::from_be_bytes(rest),
+    };
+    (mtu > 0).then_some(mtu)
+}
+
+/// CRC-32 (IEEE 802.3, as used by zlib and Ethernet).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Overwrites the last four payload bytes with a CRC32 of the rest. Payloads
+/// too short to hold the trailer are left alone.
+pub fn seal_payload(payload: &mut [u8]) {
+    let Some(body_len) = payload.len().checked_sub(CRC_LEN) else {
+        return;
+    };
+    let crc = crc32(&payload[..body_len]);
+    payload[body_len..].copy_from_slice(&crc.to_be_bytes());
+}
+
+/// Whether the CRC32 trailer written by `seal_payload` still matches the
+/// bytes in front of it.
+pub fn payload_intact(payload: &[u8]) -> bool {
+    let Some(body_len) = payload.len().checked_sub(CRC_LEN) else {
+        return false;
+    };
+    crc32(&payload[..body_len]).to_be_bytes() == payload[body_len..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_reply(identifier: u16, sequence: u16, endian: Endian) -> Vec<u8> {
+        let mut packet = vec![0u8; ICMP_HEADER_LEN + 4];
+        endian.put(&mut packet[4..6], identifier);
+        endian.put(&mut packet[6..8], sequence);
+        packet
+    }
+
+    /// An ICMPv6 Packet Too Big advertising `mtu` and quoting `echo`.
+    fn packet_too_big(mtu: u32, echo: &[u8]) -> Vec<u8> {
+        let mut packet = vec![2, 0, 0, 0];
+        packet.extend_from_slice(&mtu.to_be_bytes());
+        packet.extend_from_slice(&[0; IPV6_HEADER_LEN]);
+        packet.extend_from_slice(echo);
+        packet
+    }
+
+    #[test]
+    fn echo_fields_round_trip_in_both_byte_orders() {
+        for (endian, wire) in [(Endian::Big, [0x12, 0x34, 0x00, 0x07]), (Endian::Little, [0x34, 0x12, 0x07, 0x00])] {
+            let echo = echo_reply(0x1234, 7, endian);
+            assert_eq!(echo[4..8], wire);
+
+            let reply = parse_icmp_reply(&echo, Family::V6, false, endian).unwrap();
+            assert_eq!((reply.identifier, reply.sequence), (0x1234, 7));
+            assert_eq!(reply.rest_of_header, wire);
+
+            // The quoted echo follows `--id-endian`; the MTU never does.
+            let error = packet_too_big(1280, &echo);
+            let reply = parse_icmp_reply(&error, Family::V6, false, endian).unwrap();
+            let quoted = parse_icmp_error(&reply, Family::V6, endian).unwrap();
+            assert_eq!((quoted.identifier, quoted.sequence), (0x1234, 7));
+            assert_eq!(quoted.mtu, Some(1280));
+        }
+    }
+
+    #[test]
+    fn random_buffers_never_panic() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(131);
+        for _ in 0..4000 {
+            let mut data = vec![0u8; rng.gen_range(0..=128)];
+            rng.fill(&mut data[..]);
+            // Make a valid-looking IPv4 header likely, so the quoted-packet
+            // paths are reached as well as the early returns.
+            if data.len() > 9 && rng.gen_bool(0.5) {
+                data[0] = 0x45;
+                data[9] = IPPROTO_ICMP;
+            }
+            for family in [Family::V4, Family::V6] {
+                for ip_header in [false, true] {
+                    if let Some(reply) = parse_icmp_reply(&data, family, ip_header, Endian::Big) {
+                        parse_icmp_error(&reply, family, Endian::Big);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn short_ipv4_input_is_rejected() {
+        for len in 0..ICMP_HEADER_LEN {
+            assert!(parse_icmp_reply(&vec![0; len], Family::V4, false, Endian::Big).is_none(), "{} bytes", len);
+        }
+        for len in 0..IPV4_MIN_HEADER_LEN + ICMP_HEADER_LEN {
+            let mut data = vec![0; len];
+            if let Some(first) = data.first_mut() {
+                *first = 0x45;
+            }
+            if len > 9 {
+                data[9] = IPPROTO_ICMP;
+            }
+            assert!(parse_icmp_reply(&data, Family::V4, true, Endian::Big).is_none(), "{} bytes with an IP header", len);
+        }
+
+        let mut data = vec![0; IPV4_MIN_HEADER_LEN + ICMP_HEADER_LEN];
+        data[0] = 0x45;
+        data[9] = IPPROTO_ICMP;
+        assert!(parse_icmp_reply(&data, Family::V4, true, Endian::Big).is_some());
+        assert!(parse_icmp_reply(&data[..ICMP_HEADER_LEN], Family::V4, false, Endian::Big).is_some());
+    }
+}
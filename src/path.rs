@@ -0,0 +1,225 @@
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::asymmetry::{Outcome, Prober};
+use crate::{
+    catch_interrupt, json, ndjson, output, report_error, sleep_unless_interrupted, sweep, Options, PingStatistics, INTERRUPTED,
+};
+
+/// Deepest TTL the trace tries, as traceroute does by default.
+const MAX_HOPS: u8 = 30;
+
+/// Echo requests per TTL before a hop is written off as silent.
+const TRACE_ATTEMPTS: u32 = 2;
+
+/// Silent hops in a row after which the trace stops looking for the target.
+const MAX_SILENT_HOPS: u32 = 5;
+
+/// Rounds between two per-hop tables while monitoring.
+const REPORT_ROUNDS: u32 = 10;
+
+/// Loss at or above this percentage counts when looking for where it starts.
+const NOTABLE_LOSS: f64 = 10.0;
+
+/// Default for `--retrace`: how often the path is traced again.
+pub const DEFAULT_RETRACE: Duration = Duration::from_secs(300);
+
+/// One router, or the target itself, being monitored.
+struct Hop {
+    ttl: u8,
+    address: IpAddr,
+    identifier: u16,
+    stats: PingStatistics,
+    /// RTT of the latest round, `None` when that probe went unanswered.
+    last: Option<Duration>,
+}
+
+/// Traces the path to `target`, then pings every hop that answered in
+/// parallel once per `-i`, printing each hop's loss and RTT every few
+/// rounds (`--path-monitor`). Loss that starts at one hop and carries on to
+/// the target shows where along the path it comes from.
+///
+/// The path is traced again every `retrace`, and the hops follow it when it
+/// changes; a hop that stays keeps its statistics.
+pub fn run_path_monitor(target: IpAddr, options: &Options, retrace: Option<Duration>) {
+    let mut prober = match Prober::open(target, options) {
+        Ok(prober) => prober,
+        Err(e) => {
+            report_error(json::error("socket", target, &e), format!("Failed to create socket: {}", e));
+            return;
+        }
+    };
+
+    let (path, unreachable) = trace(&mut prober, target, options);
+    print_path(&path);
+    let mut hops = monitored_hops(&path, Vec::new(), options);
+    if hops.is_empty() {
+        say!("No hop on the path to {} answered; there is nothing to monitor.", target);
+        return;
+    }
+    let last = path.last().and_then(|(_, address)| *address);
+    if let Some(router) = last.filter(|_| unreachable) {
+        say!("{} reported {} unreachable; monitoring the path up to it.", router, target);
+    } else if last != Some(target) {
+        let hops = max_hops(options);
+        say!(
+            "{} did not answer within {} hop{}; monitoring the routers that did.",
+            target,
+            hops,
+            if hops == 1 { "" } else { "s" }
+        );
+    }
+
+    catch_interrupt();
+    let round_options = Options { count: Some(1), ..options.clone() };
+    let mut traced = Instant::now();
+    let mut rounds = 0;
+    while options.count.is_none_or(|count| rounds < count) && !INTERRUPTED.load(Ordering::Relaxed) {
+        let round_started = Instant::now();
+        ping_hops(&mut hops, &round_options);
+        rounds += 1;
+        if rounds.is_multiple_of(REPORT_ROUNDS) {
+            print_hops(target, &hops, rounds);
+        }
+
+        if retrace.is_some_and(|retrace| traced.elapsed() >= retrace) {
+            traced = Instant::now();
+            let (new_path, _) = trace(&mut prober, target, options);
+            let old: Vec<_> = hops.iter().map(|hop| (hop.ttl, hop.address)).collect();
+            hops = monitored_hops(&new_path, hops, options);
+            if hops.iter().map(|hop| (hop.ttl, hop.address)).ne(old) {
+                log_info!("path to {} changed", target);
+                say!("\nThe path to {} changed:", target);
+                print_path(&new_path);
+            }
+        }
+        sleep_unless_interrupted(round_started + options.interval);
+    }
+
+    if !rounds.is_multiple_of(REPORT_ROUNDS) || rounds == 0 {
+        print_hops(target, &hops, rounds);
+    }
+    for hop in &hops {
+        ndjson::write(&json::summary(hop.address, &hop.stats).int("hop", hop.ttl));
+    }
+}
+
+/// The deepest TTL `trace` tries.
+fn max_hops(options: &Options) -> u8 {
+    options.ttl.clamp(1, i32::from(MAX_HOPS)) as u8
+}
+
+/// The router that answered at each TTL up to the target, `None` where
+/// none did. A router reporting the target unreachable ends the path, and
+/// the second value says whether one did.
+fn trace(prober: &mut Prober, target: IpAddr, options: &Options) -> (Vec<(u8, Option<IpAddr>)>, bool) {
+    let mut path = Vec::new();
+    let mut silent = 0;
+    let mut unreachable = false;
+    for ttl in 1..=max_hops(options) {
+        let answer = (0..TRACE_ATTEMPTS).find_map(|_| match prober.probe(ttl, options) {
+            Outcome::Reply { .. } => Some(target),
+            Outcome::Expired { from, .. } => Some(from),
+            Outcome::Unreachable { from } => {
+                unreachable = true;
+                Some(from)
+            }
+            Outcome::Silent => None,
+        });
+        path.push((ttl, answer));
+        match answer {
+            Some(address) if address == target || unreachable => break,
+            Some(_) => silent = 0,
+            None => silent += 1,
+        }
+        if silent == MAX_SILENT_HOPS {
+            break;
+        }
+    }
+    // Silent hops past the last one that answered say nothing about the path.
+    while path.last().is_some_and(|(_, address)| address.is_none()) {
+        path.pop();
+    }
+    log_debug!("traced {} hops to {}", path.len(), target);
+    (path, unreachable)
+}
+
+fn print_path(path: &[(u8, Option<IpAddr>)]) {
+    for (ttl, address) in path {
+        match address {
+            Some(address) => say!("    {:>3}  {}", ttl, address),
+            None => say!("    {:>3}  *", ttl),
+        }
+    }
+}
+
+/// The hops of `path` that answered, carrying over the statistics of those
+/// in `previous` that are still at the same TTL.
+fn monitored_hops(path: &[(u8, Option<IpAddr>)], mut previous: Vec<Hop>, options: &Options) -> Vec<Hop> {
+    path.iter()
+        .filter_map(|(ttl, address)| address.map(|address| (*ttl, address)))
+        .map(|(ttl, address)| match previous.iter().position(|hop| hop.ttl == ttl && hop.address == address) {
+            Some(index) => previous.swap_remove(index),
+            None => Hop { ttl, address, identifier: rand::random(), stats: PingStatistics::for_run(options), last: None },
+        })
+        .collect()
+}
+
+/// Sends one echo request to every hop at once.
+fn ping_hops(hops: &mut [Hop], options: &Options) {
+    let rounds: Vec<PingStatistics> = thread::scope(|scope| {
+        let workers: Vec<_> = hops
+            .iter()
+            .map(|hop| {
+                let (address, identifier) = (hop.address, hop.identifier);
+                scope.spawn(move || sweep::probe_host(address, options, identifier, None).0)
+            })
+            .collect();
+        workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+    });
+    for (hop, round) in hops.iter_mut().zip(rounds) {
+        hop.last = round.rtt_range().map(|(min, _)| min);
+        hop.stats.absorb(&round);
+    }
+}
+
+fn print_hops(target: IpAddr, hops: &[Hop], rounds: u32) {
+    let width = hops.iter().map(|hop| hop.address.to_string().len()).max().unwrap_or(0).max("address".len());
+    let rtt = |value: Option<Duration>| value.map_or_else(|| "-".to_string(), output::rtt);
+
+    say!("\nPath to {} after {} rounds:", target, rounds);
+    say!("    {:>3}  {:<width$}  {:>5}  {:>5}  {:>6}  {:>9}  {:>9}  {:>9}", "hop", "address", "sent", "recv", "loss", "last", "avg", "max");
+    for hop in hops {
+        say!(
+            "    {:>3}  {:<width$}  {:>5}  {:>5}  {:>5.0}%  {:>9}  {:>9}  {:>9}",
+            hop.ttl,
+            hop.address.to_string(),
+            hop.stats.sent,
+            hop.stats.received,
+            hop.stats.loss_percent(),
+            rtt(hop.last),
+            rtt(hop.stats.average_rtt()),
+            rtt(hop.stats.rtt_range().map(|(_, max)| max))
+        );
+    }
+
+    // Routers often answer echo requests at a low priority, so loss at one
+    // hop only points at the path when every hop after it loses probes too.
+    let lossy = |hop: &Hop| hop.stats.sent > 0 && hop.stats.loss_percent() >= NOTABLE_LOSS;
+    let clean_from = hops.iter().rposition(|hop| !lossy(hop)).map_or(0, |index| index + 1);
+    match hops.get(clean_from) {
+        Some(first) if clean_from == 0 => {
+            say!("    Every hop loses probes, from the first ({}) on; the loss is likely close to this host.", first.address)
+        }
+        Some(first) => say!(
+            "    Loss starts at hop {} ({}) and carries on to the end of the path; look there first.",
+            first.ttl, first.address
+        ),
+        None if hops.iter().any(lossy) => {
+            say!("    Hops that lose probes are followed by hops that do not; those routers are likely just rate-limiting ICMP.")
+        }
+        None => {}
+    }
+}
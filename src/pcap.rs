@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::compute_checksum;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const SNAPLEN: u32 = 65535;
+/// Records start straight at the IP header, with no link layer.
+const LINKTYPE_RAW: u32 = 101;
+const ICMPV4_PROTOCOL: u8 = 1;
+const ICMPV6_NEXT_HEADER: u8 = 58;
+const IPV6_HEADER_LEN: usize = 40;
+/// Hop limit written into the IP headers ring reconstructs.
+const SYNTHETIC_TTL: u8 = 64;
+
+struct Capture {
+    file: File,
+    /// Local address the kernel routes from, by peer.
+    sources: HashMap<IpAddr, IpAddr>,
+}
+
+static CAPTURE: OnceLock<Mutex<Capture>> = OnceLock::new();
+
+/// Creates `path` and writes the pcap file header. Until this is called,
+/// `sent` and `received` return without doing anything.
+pub fn open(path: &str) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&0i32.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    file.write_all(&header)?;
+    let _ = CAPTURE.set(Mutex::new(Capture { file, sources: HashMap::new() }));
+    Ok(())
+}
+
+/// Records an ICMP message ring just sent to `destination`. The kernel adds
+/// the IP header, so one is reconstructed from the route to the destination,
+/// unless ring built it (`--spoof-source`).
+pub fn sent(destination: &socket2::SockAddr, icmp: &[u8]) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    let (Ok(mut capture), Some(destination)) = (capture.lock(), destination.as_socket()) else {
+        return;
+    };
+    if destination.is_ipv4() && icmp.first().is_some_and(|byte| byte >> 4 == 4) {
+        capture.record(icmp);
+        return;
+    }
+    let source = capture.source_towards(destination);
+    let mut packet = with_ip_header(source, destination.ip(), icmp, None);
+    // The kernel computes ICMPv6 checksums on the way out, whatever ring put
+    // in the field; do the same so the capture does not flag every request.
+    if let (IpAddr::V6(source), IpAddr::V6(destination), true) = (source, destination.ip(), icmp.len() >= 4) {
+        let start = IPV6_HEADER_LEN;
+        packet[start + 2..start + 4].fill(0);
+        let checksum = icmpv6_checksum(source, destination, &packet[start..]);
+        packet[start + 2..start + 4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    capture.record(&packet);
+}
+
+/// Records a datagram ring read from `source`. Raw IPv4 sockets deliver the
+/// IP header and it is kept as received; otherwise one is reconstructed.
+pub fn received(source: Option<SocketAddr>, data: &[u8], ttl: Option<u8>) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    let Ok(mut capture) = capture.lock() else {
+        return;
+    };
+
+    let Some(source) = source else {
+        return;
+    };
+    // An ICMP type never has the IP version in its top nibble, so a leading
+    // 4 or 6 means the kernel handed over the whole packet.
+    let version = if source.is_ipv4() { 4 } else { 6 };
+    if data.first().is_some_and(|byte| byte >> 4 == version) {
+        capture.record(data);
+        return;
+    }
+    let local = capture.source_towards(source);
+    let packet = with_ip_header(source.ip(), local, data, ttl);
+    capture.record(&packet);
+}
+
+/// Records an ICMP error the kernel reported through the socket error queue
+/// rather than as a datagram. Only the quoted probe and the error's type,
+/// code and MTU survive, so the message around them is rebuilt.
+pub fn queued_error(from: IpAddr, target: IpAddr, icmp_type: u8, code: u8, mtu: Option<u32>, quoted: &[u8]) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    let Ok(mut capture) = capture.lock() else {
+        return;
+    };
+    let local = capture.source_towards(SocketAddr::new(from, 0));
+    let mut icmp = vec![icmp_type, code, 0, 0];
+    icmp.extend_from_slice(&mtu.unwrap_or(0).to_be_bytes());
+    icmp.extend_from_slice(&with_ip_header(local, target, quoted, None));
+    let checksum = match (local, from) {
+        (IpAddr::V6(local), IpAddr::V6(from)) => icmpv6_checksum(from, local, &icmp),
+        _ => compute_checksum(&icmp),
+    };
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+    let packet = with_ip_header(from, local, &icmp, None);
+    capture.record(&packet);
+}
+
+impl Capture {
+    fn source_towards(&mut self, peer: SocketAddr) -> IpAddr {
+        *self.sources.entry(peer.ip()).or_insert_with(|| route_source(peer))
+    }
+
+    fn record(&mut self, packet: &[u8]) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let length = packet.len().min(SNAPLEN as usize);
+        let mut record = Vec::with_capacity(16 + length);
+        record.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&now.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(length as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&packet[..length]);
+        // One write per packet keeps the file readable if ring is killed.
+        if let Err(e) = self.file.write_all(&record) {
+            log_warn!("failed to write packet capture: {}", e);
+        }
+    }
+}
+
+/// Asks the kernel which local address it would send from, without sending
+/// anything: connecting a UDP socket only selects a route.
+fn route_source(peer: SocketAddr) -> IpAddr {
+    let unspecified = match peer {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let mut discard = peer;
+    discard.set_port(9);
+    UdpSocket::bind(SocketAddr::new(unspecified, 0))
+        .and_then(|socket| socket.connect(discard).and_then(|_| socket.local_addr()))
+        .map_or(unspecified, |local| local.ip())
+}
+
+fn with_ip_header(source: IpAddr, destination: IpAddr, icmp: &[u8], ttl: Option<u8>) -> Vec<u8> {
+    let ttl = ttl.unwrap_or(SYNTHETIC_TTL);
+    match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let mut packet = vec![0x45, 0];
+            packet.extend_from_slice(&((20 + icmp.len()) as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0, 0, ttl, ICMPV4_PROTOCOL, 0, 0]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+            let checksum = compute_checksum(&packet);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+            packet.extend_from_slice(icmp);
+            packet
+        }
+        (source, destination) => {
+            let mut packet = vec![0x60, 0, 0, 0];
+            packet.extend_from_slice(&(icmp.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[ICMPV6_NEXT_HEADER, ttl]);
+            packet.extend_from_slice(&v6(source).octets());
+            packet.extend_from_slice(&v6(destination).octets());
+            packet.extend_from_slice(icmp);
+            packet
+        }
+    }
+}
+
+fn v6(address: IpAddr) -> Ipv6Addr {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped(),
+        IpAddr::V6(address) => address,
+    }
+}
+
+/// RFC 4443 section 2.3: the checksum covers a pseudo-header as well.
+fn icmpv6_checksum(source: Ipv6Addr, destination: Ipv6Addr, icmp: &[u8]) -> u16 {
+    let mut pseudo = Vec::with_capacity(40 + icmp.len());
+    pseudo.extend_from_slice(&source.octets());
+    pseudo.extend_from_slice(&destination.octets());
+    pseudo.extend_from_slice(&(icmp.len() as u32).to_be_bytes());
+    pseudo.extend_from_slice(&[0, 0, 0, ICMPV6_NEXT_HEADER]);
+    pseudo.extend_from_slice(icmp);
+    compute_checksum(&pseudo)
+}
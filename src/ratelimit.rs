@@ -0,0 +1,29 @@
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// A token bucket shared by worker threads, refilled at `rate` tokens per
+/// second and holding at most one token, so callers are spaced evenly
+/// instead of bursting after an idle spell.
+pub struct RateLimiter {
+    interval: Duration,
+    next_token: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> RateLimiter {
+        RateLimiter { interval: Duration::from_secs_f64(1.0 / rate), next_token: Mutex::new(Instant::now()) }
+    }
+
+    /// Blocks until the caller may send one probe.
+    pub fn acquire(&self) {
+        let wait = {
+            let mut next_token = self.next_token.lock().unwrap();
+            let now = Instant::now();
+            let slot = (*next_token).max(now);
+            *next_token = slot + self.interval;
+            slot - now
+        };
+        sleep(wait);
+    }
+}
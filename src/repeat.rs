@@ -0,0 +1,69 @@
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::{
+    json, ndjson, print_statistics, run_probes, sleep_unless_interrupted, webhook, ExitReason, Options, PingStatistics, Probe,
+    INTERRUPTED,
+};
+
+/// Loss of the best and worst run so far, and how many runs lost anything.
+struct RunLoss {
+    best: f64,
+    worst: f64,
+    lossy: u32,
+}
+
+/// Repeats the whole count-based run against `target` `runs` times, `gap`
+/// apart (`--repeat-runs`, `--run-gap`). Each run prints its own summary;
+/// a grand summary over all of them follows.
+///
+/// Only the running totals are kept between runs, and their RTT samples are
+/// capped by `--max-samples` like a single run's, so memory does not grow
+/// with the number of runs. Returns the totals.
+pub fn run_repeated(target: IpAddr, probe: Probe, options: &Options, runs: u32, gap: Duration) -> PingStatistics {
+    let mut total = PingStatistics::for_run(options);
+    let mut loss = RunLoss { best: f64::MAX, worst: 0.0, lossy: 0 };
+    let mut completed = 0;
+
+    for run in 1..=runs {
+        if run > 1 {
+            if !gap.is_zero() {
+                say!("\nWaiting {:.1}s before the next run...", gap.as_secs_f64());
+            }
+            sleep_unless_interrupted(Instant::now() + gap);
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                total.exit_reason = ExitReason::Interrupted;
+                break;
+            }
+        }
+        say!("\nRun {} of {}:", run, runs);
+
+        let stats = run_probes(target, probe, options);
+        completed += 1;
+        let run_loss = stats.loss_percent();
+        loss.best = loss.best.min(run_loss);
+        loss.worst = loss.worst.max(run_loss);
+        if stats.lost() > 0 {
+            loss.lossy += 1;
+        }
+        total.absorb(&stats);
+        if stats.exit_reason != ExitReason::CountReached {
+            break;
+        }
+    }
+
+    say!("\n{} of {} runs done; the totals across them follow.", completed, runs);
+    print_statistics(target, &total);
+    if completed > 0 {
+        say!(
+            "    Runs: loss per run from {:.0}% to {:.0}%; {} of {} runs lost probes",
+            loss.best, loss.worst, loss.lossy, completed
+        );
+    }
+
+    let record = json::summary(target, &total).int("runs", completed).int("lossy_runs", loss.lossy);
+    ndjson::write(&record);
+    webhook::post(&record);
+    total
+}
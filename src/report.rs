@@ -0,0 +1,98 @@
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+
+use crate::syslog::{self, Severity};
+use crate::{PingStatistics, Reply};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+#[cfg(unix)]
+static STREAM: OnceLock<Mutex<UnixStream>> = OnceLock::new();
+
+/// Connects to the supervising process listening on `path`. Every later
+/// `send` writes one message framed by a 4-byte big-endian length.
+#[cfg(unix)]
+pub fn connect(path: &str) -> io::Result<()> {
+    let stream = UnixStream::connect(path)?;
+    let _ = STREAM.set(Mutex::new(stream));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn connect(_path: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets are not available on this platform"))
+}
+
+/// Sends one message if a report socket is connected. A supervisor that
+/// went away is logged once and then ignored so the run can continue.
+pub fn send(message: &str) {
+    #[cfg(unix)]
+    if let Some(stream) = STREAM.get() {
+        let Ok(mut stream) = stream.lock() else {
+            return;
+        };
+
+        let mut frame = Vec::with_capacity(4 + message.len());
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message.as_bytes());
+
+        if let Err(e) = stream.write_all(&frame) {
+            static REPORTED: OnceLock<()> = OnceLock::new();
+            if REPORTED.set(()).is_ok() {
+                log_warn!("report socket write failed: {}", e);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = message;
+}
+
+/// Sends `message` to the report socket and, with `--syslog`, the system log.
+fn event(severity: Severity, message: &str) {
+    send(message);
+    syslog::log(severity, message);
+}
+
+pub fn reply(target: IpAddr, reply: &Reply) {
+    let message = format!(
+        "reply target={} seq={} from={} bytes={} rtt_ms={:.3}",
+        target,
+        reply.sequence,
+        reply.source,
+        reply.bytes,
+        reply.rtt.as_secs_f64() * 1000.0
+    );
+    event(Severity::Info, &message);
+}
+
+pub fn timeout(target: IpAddr, sequence: u16) {
+    event(Severity::Warning, &format!("timeout target={} seq={}", target, sequence));
+}
+
+/// A target that started or stopped answering during a run.
+pub fn state(target: IpAddr, up: bool) {
+    let (severity, status) = if up { (Severity::Info, "up") } else { (Severity::Error, "down") };
+    event(severity, &format!("state target={} status={}", target, status));
+}
+
+pub fn summary(target: IpAddr, stats: &PingStatistics) {
+    let mut message = format!(
+        "summary target={} sent={} received={} loss={:.1}",
+        target,
+        stats.sent,
+        stats.received,
+        stats.loss_percent()
+    );
+    if let (Some(average), Some((min, max))) = (stats.average_rtt(), stats.rtt_range()) {
+        message.push_str(&format!(
+            " min_ms={:.3} avg_ms={:.3} max_ms={:.3}",
+            min.as_secs_f64() * 1000.0,
+            average.as_secs_f64() * 1000.0,
+            max.as_secs_f64() * 1000.0
+        ));
+    }
+    event(if stats.received > 0 { Severity::Info } else { Severity::Error }, &message);
+}
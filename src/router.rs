@@ -0,0 +1,164 @@
+#[cfg(unix)]
+use std::ffi::CString;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::time::{Duration, Instant};
+
+use crate::packet::{parse_icmp_reply, Endian, Family, ParsedReply};
+use crate::{compute_checksum, create_socket, json, output, pcap, receive_before, report_error, Options, SocketKind};
+
+const ROUTER_SOLICITATION: u8 = 133;
+const ROUTER_ADVERTISEMENT: u8 = 134;
+const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+/// Neighbor Discovery messages must be sent and received with hop limit 255
+/// (RFC 4861), which proves they did not cross a router.
+pub const ND_HOP_LIMIT: u32 = 255;
+const OPTION_PREFIX_INFORMATION: u8 = 3;
+
+/// A prefix carried in a Prefix Information option.
+struct Prefix {
+    network: Ipv6Addr,
+    length: u8,
+    valid_lifetime: u32,
+}
+
+/// The parts of a Router Advertisement worth printing.
+struct Advertisement {
+    hop_limit: u8,
+    lifetime: u16,
+    prefixes: Vec<Prefix>,
+}
+
+/// Sends a Router Solicitation out of `interface` and lists every router
+/// that answers with an advertisement before the timeout.
+pub fn run_router_solicitation(interface: &str, options: &Options) {
+    let Some(index) = interface_index(interface) else {
+        report_error(
+            json::error("interface", interface, "unknown network interface"),
+            format!("Unknown network interface: {}", interface),
+        );
+        return;
+    };
+
+    let target = IpAddr::V6(ALL_ROUTERS);
+    let (socket, kind) = match create_socket(target, options) {
+        Ok(created) => created,
+        Err(e) => {
+            report_error(json::error("socket", target, &e), format!("Failed to create socket: {}", e));
+            return;
+        }
+    };
+    if kind == SocketKind::Dgram {
+        report_error(
+            json::error("socket", target, "needs a raw socket"),
+            "Router solicitation needs a raw socket; run ring with elevated privileges.".to_string(),
+        );
+        return;
+    }
+    if let Err(e) = socket.set_multicast_hops_v6(ND_HOP_LIMIT).and_then(|_| socket.set_multicast_if_v6(index)) {
+        report_error(
+            json::error("socket", interface, &e),
+            format!("Failed to prepare {} for router solicitation: {}", interface, e),
+        );
+        return;
+    }
+
+    let mut packet = vec![ROUTER_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+    let checksum = compute_checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    let dest_addr = socket2::SockAddr::from(SocketAddr::V6(SocketAddrV6::new(ALL_ROUTERS, 0, 0, index)));
+    let start = Instant::now();
+    if let Err(e) = socket.send_to(&packet, &dest_addr) {
+        report_error(json::error("send", target, &e), format!("Failed to send router solicitation: {}", e));
+        return;
+    }
+    pcap::sent(&dest_addr, &packet);
+
+    let deadline = start + Duration::from_millis(options.timeout as u64);
+    let mut buffer = [0u8; 1500];
+    let mut routers: Vec<IpAddr> = Vec::new();
+
+    while let Ok(Some(received)) = receive_before(&socket, &mut buffer, deadline) {
+        let Some(source) = received.source.map(|source| source.ip()) else {
+            continue;
+        };
+        let Some(icmp) = parse_icmp_reply(&buffer[..received.len], Family::V6, false, Endian::current()) else {
+            continue;
+        };
+        if icmp.icmp_type != ROUTER_ADVERTISEMENT || icmp.code != 0 || received.ttl.is_some_and(|ttl| ttl != 255) {
+            continue;
+        }
+        let Some(advertisement) = parse_advertisement(&icmp) else {
+            continue;
+        };
+        if routers.contains(&source) {
+            continue;
+        }
+        routers.push(source);
+
+        let scope = match source {
+            IpAddr::V6(ip) if ip.is_unicast_link_local() => format!("%{}", interface),
+            _ => String::new(),
+        };
+        say!(
+            "Router {}{}: time={} lifetime={}s hop-limit={}",
+            source,
+            scope,
+            output::rtt(start.elapsed()),
+            advertisement.lifetime,
+            advertisement.hop_limit
+        );
+        for prefix in &advertisement.prefixes {
+            say!("    prefix {}/{} valid={}s", prefix.network, prefix.length, prefix.valid_lifetime);
+        }
+    }
+
+    say!("\n{} router(s) answered on {}.", routers.len(), interface);
+}
+
+/// Reads the fixed fields and Prefix Information options of an RA.
+fn parse_advertisement(icmp: &ParsedReply) -> Option<Advertisement> {
+    // The rest of the header holds the hop limit, the flags and the router
+    // lifetime; reachable time and retransmit timer take the first 8
+    // payload bytes.
+    let rest = icmp.rest_of_header;
+    let hop_limit = rest[0];
+    let lifetime = u16::from_be_bytes([rest[2], rest[3]]);
+    let mut rest = icmp.payload.get(8..)?;
+    let mut prefixes = Vec::new();
+
+    while rest.len() >= 2 {
+        let length = rest[1] as usize * 8;
+        if length == 0 {
+            return None;
+        }
+        let option = rest.get(..length)?;
+
+        if option[0] == OPTION_PREFIX_INFORMATION && length >= 32 {
+            let mut network = [0u8; 16];
+            network.copy_from_slice(&option[16..32]);
+            prefixes.push(Prefix {
+                network: Ipv6Addr::from(network),
+                length: option[2],
+                valid_lifetime: u32::from_be_bytes([option[4], option[5], option[6], option[7]]),
+            });
+        }
+        rest = &rest[length..];
+    }
+
+    Some(Advertisement { hop_limit, lifetime, prefixes })
+}
+
+#[cfg(unix)]
+pub fn interface_index(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
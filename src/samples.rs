@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Default for `--max-samples`: a day of probes at the default interval.
+pub const DEFAULT_MAX_SAMPLES: usize = 86_400;
+
+/// Individual RTTs kept for quantiles, capped at `limit` entries so that a
+/// long `-t` run stays at a flat memory footprint.
+///
+/// Once more replies than `limit` have been seen, reservoir sampling keeps a
+/// uniform random subset, so quantiles become estimates. Minimum, maximum and
+/// average are tracked separately and stay exact.
+pub struct RttSamples {
+    samples: Vec<Duration>,
+    limit: usize,
+    seen: u64,
+}
+
+impl RttSamples {
+    pub fn new(limit: usize) -> RttSamples {
+        RttSamples { samples: Vec::new(), limit, seen: 0 }
+    }
+
+    pub fn push(&mut self, rtt: Duration) {
+        self.seen += 1;
+        if self.samples.len() < self.limit {
+            self.samples.push(rtt);
+            return;
+        }
+
+        let slot = rand::thread_rng().gen_range(0..self.seen);
+        if let Some(sample) = self.samples.get_mut(slot as usize) {
+            *sample = rtt;
+        }
+    }
+
+    /// The RTTs currently held, in no particular order.
+    pub fn values(&self) -> &[Duration] {
+        &self.samples
+    }
+
+    /// Whether some replies were dropped from the sample set.
+    pub fn is_sampled(&self) -> bool {
+        self.seen > self.samples.len() as u64
+    }
+
+    /// Nearest-rank percentile, `pct` in 0..=100.
+    pub fn percentile(&self, pct: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+
+    /// Standard deviation of the held samples, which ping reports as `mdev`.
+    pub fn mdev(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let n = self.samples.len() as f64;
+        let mean = self.samples.iter().map(Duration::as_secs_f64).sum::<f64>() / n;
+        let variance = self.samples.iter().map(|rtt| (rtt.as_secs_f64() - mean).powi(2)).sum::<f64>() / n;
+        Some(Duration::from_secs_f64(variance.sqrt()))
+    }
+
+    /// Mean after discarding `pct` percent of the samples from each end,
+    /// `pct` below 50. At least one sample always remains.
+    pub fn trimmed_mean(&self, pct: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        let trim = (((pct / 100.0) * sorted.len() as f64).floor() as usize).min((sorted.len() - 1) / 2);
+        let kept = &sorted[trim..sorted.len() - trim];
+        Some(kept.iter().sum::<Duration>() / kept.len() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_samples_have_no_statistics() {
+        let samples = RttSamples::new(8);
+        assert_eq!(samples.percentile(50.0), None);
+        assert_eq!(samples.mdev(), None);
+        assert_eq!(samples.trimmed_mean(10.0), None);
+        assert!(!samples.is_sampled());
+    }
+
+    #[test]
+    fn one_sample_is_every_statistic() {
+        let rtt = Duration::from_micros(1500);
+        let mut samples = RttSamples::new(8);
+        samples.push(rtt);
+        assert_eq!(samples.percentile(50.0), Some(rtt));
+        assert_eq!(samples.percentile(95.0), Some(rtt));
+        assert_eq!(samples.mdev(), Some(Duration::ZERO));
+        assert_eq!(samples.trimmed_mean(10.0), Some(rtt));
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::{
+    compute_checksum, create_icmp_packet, create_socket, is_reply_to, output, parse_received, pcap, receive_before,
+    Options, ICMP_HEADER_LEN,
+};
+
+const ICMPV6_NEXT_HEADER: u8 = 58;
+
+/// Pings both loopback addresses once and checks every stage of the socket
+/// path along the way, printing one line per step. Returns the process exit
+/// status: 0 when every family that is available passed.
+///
+/// Loopback never leaves the host, so a failure here points at ring, its
+/// privileges or the local stack rather than the network.
+pub fn run_self_test(options: &Options) -> i32 {
+    println!("ring self-test:");
+    let mut failed = false;
+    for target in [IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)] {
+        println!("  {}:", target);
+        failed |= !check(target, options);
+    }
+
+    if failed {
+        println!("Self-test FAILED.");
+        1
+    } else {
+        println!("Self-test passed.");
+        0
+    }
+}
+
+/// Runs the steps for one address. An address family the host does not
+/// support at all is reported and skipped rather than failed.
+fn check(target: IpAddr, options: &Options) -> bool {
+    let (socket, kind) = match create_socket(target, options) {
+        Ok(created) => created,
+        Err(e) if target.is_ipv6() && e.raw_os_error() == Some(libc::EAFNOSUPPORT) => {
+            step("skip", format!("IPv6 is not available on this host ({})", e));
+            return true;
+        }
+        Err(e) => return fail(format!("socket could not be created: {}", e)),
+    };
+    step("ok", format!("socket created ({})", kind.name()));
+
+    // A fixed seed gives a recognisable payload to compare the echo against.
+    let identifier = std::process::id() as u16;
+    let mut rng = StdRng::seed_from_u64(u64::from(identifier));
+    let packet = create_icmp_packet(options.packet_size, target, identifier, 1, Some(&mut rng), false);
+    let start = Instant::now();
+    let dest_addr = SocketAddr::new(target, 0).into();
+    if let Err(e) = socket.send_to(&packet, &dest_addr) {
+        return fail(format!("echo request could not be sent: {}", e));
+    }
+    pcap::sent(&dest_addr, &packet);
+    step("ok", format!("echo request sent (seq=1, {} bytes of data)", options.packet_size));
+
+    let deadline = start + Duration::from_millis(options.timeout as u64);
+    let mut buffer = [0u8; 2048];
+    loop {
+        let received = match receive_before(&socket, &mut buffer, deadline) {
+            Ok(Some(received)) => received,
+            Ok(None) => return fail(format!("no matching reply within {}ms", options.timeout)),
+            Err(e) => return fail(format!("receive failed: {}", e)),
+        };
+        let data = &buffer[..received.len];
+        let Some(icmp) = parse_received(data, kind, target) else {
+            continue;
+        };
+        if !is_reply_to(&icmp, &packet, kind, target, false) {
+            continue;
+        }
+        step("ok", format!("reply matched identifier and sequence in {}", output::rtt(start.elapsed())));
+
+        // The ICMP message is whatever follows the IPv4 header, if any.
+        let message = &data[data.len() - ICMP_HEADER_LEN - icmp.payload.len()..];
+        if !checksum_valid(target, message) {
+            return fail("reply checksum is invalid");
+        }
+        step("ok", "reply checksum valid");
+
+        if icmp.payload != &packet[ICMP_HEADER_LEN..] {
+            return fail(format!("reply payload differs from the request ({} of {} bytes)", icmp.payload.len(), options.packet_size));
+        }
+        step("ok", "payload intact");
+        return true;
+    }
+}
+
+fn checksum_valid(target: IpAddr, message: &[u8]) -> bool {
+    match target {
+        IpAddr::V4(_) => compute_checksum(message) == 0,
+        // ICMPv6 checksums cover a pseudo-header (RFC 4443 section 2.3);
+        // a loopback reply goes from and to the same address.
+        IpAddr::V6(ip) => {
+            let mut pseudo = Vec::with_capacity(40 + message.len());
+            pseudo.extend_from_slice(&ip.octets());
+            pseudo.extend_from_slice(&ip.octets());
+            pseudo.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, ICMPV6_NEXT_HEADER]);
+            pseudo.extend_from_slice(message);
+            compute_checksum(&pseudo) == 0
+        }
+    }
+}
+
+fn step(status: &str, message: impl fmt::Display) {
+    println!("    [{}] {}", status, message);
+}
+
+fn fail(message: impl fmt::Display) -> bool {
+    step("FAIL", message);
+    false
+}
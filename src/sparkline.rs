@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::output;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Fixed-size window of recent probe outcomes rendered as a unicode
+/// sparkline. Lost probes are kept as gaps so loss shows up in the graph.
+pub struct Sparkline {
+    samples: VecDeque<Option<Duration>>,
+    width: usize,
+}
+
+impl Sparkline {
+    pub fn new(width: usize) -> Sparkline {
+        Sparkline { samples: VecDeque::with_capacity(width), width }
+    }
+
+    pub fn push(&mut self, rtt: Option<Duration>) {
+        if self.samples.len() == self.width {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+    }
+
+    pub fn render(&self) -> String {
+        let received = self.samples.iter().flatten();
+        let min = received.clone().min().copied().unwrap_or_default();
+        let max = received.clone().max().copied().unwrap_or_default();
+        let span = (max - min).as_secs_f64();
+
+        let graph: String = self
+            .samples
+            .iter()
+            .map(|sample| match sample {
+                Some(rtt) if span > 0.0 => {
+                    let level = (*rtt - min).as_secs_f64() / span * (BARS.len() - 1) as f64;
+                    BARS[level.round() as usize]
+                }
+                Some(_) => BARS[0],
+                None => ' ',
+            })
+            .collect();
+
+        format!("[{}] {}-{}", graph, output::rtt_value(min), output::rtt(max))
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ratelimit::RateLimiter;
+use crate::{
+    create_icmp_packet, create_socket, json, ndjson, next_sequence, output, payload_rng, send_and_receive_ring,
+    sleep_until_next_probe, Options, PingStatistics,
+};
+
+/// Largest sweep accepted without `--force`.
+pub const DEFAULT_MAX_HOSTS: u128 = 1024;
+
+/// Hard ceiling even with `--force`, since every address is held in memory.
+pub const ABSOLUTE_MAX_HOSTS: u128 = 1 << 20;
+
+/// The fraction of a configured rate below which it counts as not sustained.
+const SUSTAINED_RATE: f64 = 0.9;
+
+/// Politeness limits for a sweep.
+pub struct Pacing {
+    /// `--max-pps`: probes per second across all workers.
+    pub max_pps: Option<f64>,
+    /// `--per-host-rate`: probes per second to any one host.
+    pub per_host_rate: Option<f64>,
+}
+
+/// Parses `address/prefix` into the network address and prefix length.
+pub fn parse_cidr(value: &str) -> Option<(IpAddr, u32)> {
+    let (address, prefix) = value.split_once('/')?;
+    let address = address.parse::<IpAddr>().ok()?;
+    let prefix = prefix.parse::<u32>().ok()?;
+
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    if prefix > bits {
+        return None;
+    }
+    Some((address, prefix))
+}
+
+/// Number of addresses `expand_cidr` yields for the range. The network and
+/// broadcast addresses are skipped for IPv4 ranges larger than a /31, and the
+/// subnet-router anycast address for IPv6 ranges larger than a /127.
+pub fn host_count(network: IpAddr, prefix: u32) -> u128 {
+    let (bits, point_to_point) = match network {
+        IpAddr::V4(_) => (32, 31),
+        IpAddr::V6(_) => (128, 127),
+    };
+    let size = 1u128.checked_shl(bits - prefix).unwrap_or(u128::MAX);
+
+    match (network, prefix >= point_to_point) {
+        (_, true) => size,
+        (IpAddr::V4(_), false) => size - 2,
+        (IpAddr::V6(_), false) => size - 1,
+    }
+}
+
+pub fn expand_cidr(network: IpAddr, prefix: u32) -> Vec<IpAddr> {
+    match network {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            let base = u32::from(ip) & mask;
+            let last = base | !mask;
+            let (first, last) = if prefix >= 31 { (base, last) } else { (base + 1, last - 1) };
+            (first..=last).map(|host| IpAddr::V4(Ipv4Addr::from(host))).collect()
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            let base = u128::from(ip) & mask;
+            let last = base | !mask;
+            let first = if prefix >= 127 { base } else { base + 1 };
+            (first..=last).map(|host| IpAddr::V6(Ipv6Addr::from(host))).collect()
+        }
+    }
+}
+
+/// Pings every host in `hosts` using up to `parallel` worker threads and
+/// prints the hosts that answered. `pacing` caps the overall and per-host
+/// probe rates; the workers share one limiter for the overall rate.
+pub fn run_sweep(range: &str, hosts: &[IpAddr], options: &Options, parallel: usize, pacing: &Pacing) {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(hosts.len()));
+    let limiter = pacing.max_pps.map(RateLimiter::new);
+    let slow_hosts = AtomicUsize::new(0);
+    let options = match pacing.per_host_rate {
+        Some(rate) => Options { interval: options.interval.max(Duration::from_secs_f64(1.0 / rate)), ..options.clone() },
+        None => options.clone(),
+    };
+    let started = Instant::now();
+
+    thread::scope(|scope| {
+        for _ in 0..parallel.clamp(1, hosts.len().max(1)) {
+            scope.spawn(|| {
+                let mut rng = rand::thread_rng();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(host) = hosts.get(index) else {
+                        break;
+                    };
+
+                    // Workers share the wire on raw sockets, so each host gets
+                    // its own identifier to keep replies apart.
+                    let (stats, kept_pace) = probe_host(*host, &options, rand::Rng::gen(&mut rng), limiter.as_ref());
+                    if !kept_pace {
+                        slow_hosts.fetch_add(1, Ordering::Relaxed);
+                    }
+                    results.lock().unwrap().push((index, stats));
+                }
+            });
+        }
+    });
+
+    let elapsed = started.elapsed();
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    for (index, stats) in &results {
+        ndjson::write(&json::summary(hosts[*index], stats));
+    }
+    let up: Vec<_> = results.iter().filter(|(_, stats)| stats.received > 0).collect();
+
+    let width = up.iter().map(|(index, _)| hosts[*index].to_string().len()).max().unwrap_or(0);
+
+    say!("\nring sweep of {}: {} hosts, {} up", range, hosts.len(), up.len());
+    for (index, stats) in up {
+        let average = stats.average_rtt().unwrap_or_default();
+        say!(
+            "    {:<width$}  up  {}/{} replies, avg={}",
+            hosts[*index].to_string(),
+            stats.received,
+            stats.sent,
+            output::rtt(average)
+        );
+    }
+
+    if let Some(max_pps) = pacing.max_pps {
+        let sent: u32 = results.iter().map(|(_, stats)| stats.sent).sum();
+        let achieved = f64::from(sent) / elapsed.as_secs_f64().max(f64::EPSILON);
+        say!("    rate: {:.1} probes/s (limit {})", achieved, max_pps);
+        if achieved < max_pps * SUSTAINED_RATE {
+            log_warn!("sweep of {} reached {:.1} probes/s of the --max-pps {}", range, achieved, max_pps);
+            say!("    --max-pps {} could not be sustained; raise --parallel or lower -w so workers spend less time waiting.", max_pps);
+        }
+    }
+    if let Some(rate) = pacing.per_host_rate {
+        let slow_hosts = slow_hosts.into_inner();
+        if slow_hosts > 0 {
+            log_warn!("--per-host-rate {} could not be sustained for {} hosts in {}", rate, slow_hosts, range);
+            say!(
+                "    --per-host-rate {} could not be sustained for {} hosts; replies, timeouts or --max-pps held their probes back.",
+                rate, slow_hosts
+            );
+        }
+    }
+}
+
+/// Sends `count` probes to one host without printing per-reply lines. Also
+/// returns whether the probes kept to the `-i` spacing, which they cannot
+/// when a reply, a timeout or the shared limiter takes longer than the interval.
+pub fn probe_host(target: IpAddr, options: &Options, identifier: u16, limiter: Option<&RateLimiter>) -> (PingStatistics, bool) {
+    let mut stats = PingStatistics::new();
+    let (socket, kind) = match create_socket(target, options) {
+        Ok(created) => created,
+        Err(e) => {
+            log_warn!("failed to create socket for {}: {}", target, e);
+            return (stats, true);
+        }
+    };
+
+    let dest_addr = SocketAddr::new(target, 0);
+    let recent_ids = VecDeque::from([identifier]);
+    let mut payload_rng = payload_rng(options);
+    // A sweep always ends: an unlimited count probes each host once.
+    let count = options.count.unwrap_or(1).max(1);
+    let mut sequence = 0;
+
+    let mut first_sent = None;
+    let mut last_sent = Instant::now();
+    for probe in 1..=count {
+        sequence = next_sequence(sequence, options);
+        let probe_started = Instant::now();
+        if let Some(limiter) = limiter {
+            limiter.acquire();
+        }
+        last_sent = Instant::now();
+        first_sent.get_or_insert(last_sent);
+        let packet = create_icmp_packet(options.packet_size, target, identifier, sequence, payload_rng.as_mut(), options.payload_crc);
+        if let Ok(reply) = send_and_receive_ring(&socket, kind, &packet, &dest_addr, &recent_ids, options) {
+            stats.record_reply(reply.rtt);
+        }
+        stats.sent += 1;
+
+        if probe < count {
+            sleep_until_next_probe(probe_started, options.interval);
+        }
+    }
+
+    log_debug!("sweep probe of {} finished: {}/{} replies", target, stats.received, stats.sent);
+    let spread = last_sent - first_sent.unwrap_or(last_sent);
+    let planned = options.interval * (count - 1);
+    (stats, spread.as_secs_f64() * SUSTAINED_RATE <= planned.as_secs_f64())
+}
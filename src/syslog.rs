@@ -0,0 +1,79 @@
+use std::io;
+
+#[cfg(unix)]
+use std::ffi::CString;
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+/// How serious a message is; mapped onto the syslog priorities.
+#[derive(Clone, Copy)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// The `--syslog-tag` ident. `openlog` keeps the pointer rather than a copy,
+/// so the string has to live as long as the process.
+#[cfg(unix)]
+static IDENT: OnceLock<CString> = OnceLock::new();
+
+/// Facilities `--syslog-facility` accepts.
+#[cfg(unix)]
+const FACILITIES: [(&str, libc::c_int); 10] = [
+    ("user", libc::LOG_USER),
+    ("daemon", libc::LOG_DAEMON),
+    ("local0", libc::LOG_LOCAL0),
+    ("local1", libc::LOG_LOCAL1),
+    ("local2", libc::LOG_LOCAL2),
+    ("local3", libc::LOG_LOCAL3),
+    ("local4", libc::LOG_LOCAL4),
+    ("local5", libc::LOG_LOCAL5),
+    ("local6", libc::LOG_LOCAL6),
+    ("local7", libc::LOG_LOCAL7),
+];
+
+/// Opens the system log under `tag` and `facility` (`user`, `daemon` or
+/// `local0` to `local7`). Every later `log` goes there.
+#[cfg(unix)]
+pub fn open(facility: &str, tag: &str) -> io::Result<()> {
+    let Some((_, facility)) = FACILITIES.iter().find(|(name, _)| name.eq_ignore_ascii_case(facility)) else {
+        let names: Vec<&str> = FACILITIES.iter().map(|(name, _)| *name).collect();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown facility {} (expected one of {})", facility, names.join(", ")),
+        ));
+    };
+    let ident = CString::new(tag).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "the tag contains a NUL byte"))?;
+    let ident = IDENT.get_or_init(|| ident);
+    unsafe { libc::openlog(ident.as_ptr(), libc::LOG_PID, *facility) };
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn open(_facility: &str, _tag: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "syslog is not available on this platform"))
+}
+
+/// Writes one message if `open` succeeded; a no-op otherwise.
+pub fn log(severity: Severity, message: &str) {
+    #[cfg(unix)]
+    {
+        if IDENT.get().is_none() {
+            return;
+        }
+        let Ok(message) = CString::new(message) else {
+            return;
+        };
+        let priority = match severity {
+            Severity::Info => libc::LOG_INFO,
+            Severity::Warning => libc::LOG_WARNING,
+            Severity::Error => libc::LOG_ERR,
+        };
+        // The message goes through "%s" so a `%` in it is never a directive.
+        unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+    }
+
+    #[cfg(not(unix))]
+    let _ = (severity, message);
+}
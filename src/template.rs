@@ -0,0 +1,89 @@
+use crate::{output, Reply};
+
+#[derive(Clone, Copy)]
+enum Field {
+    Sequence,
+    From,
+    Rtt,
+    Ttl,
+    Bytes,
+    Tos,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        match name {
+            "seq" => Some(Field::Sequence),
+            "from" => Some(Field::From),
+            "rtt" => Some(Field::Rtt),
+            "ttl" => Some(Field::Ttl),
+            "bytes" => Some(Field::Bytes),
+            "tos" => Some(Field::Tos),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Segment {
+    Literal(String),
+    Placeholder(Field),
+}
+
+/// A `--format` string such as `"{seq} {from} {rtt}ms ttl={ttl}"`, parsed
+/// once at startup so unknown placeholders are rejected before any probe.
+#[derive(Clone)]
+pub struct ReplyTemplate {
+    segments: Vec<Segment>,
+}
+
+impl ReplyTemplate {
+    pub fn parse(format: &str) -> Result<ReplyTemplate, String> {
+        let mut segments = Vec::new();
+        let mut rest = format;
+
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                segments.push(Segment::Literal(rest[..open].to_string()));
+            }
+
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in \"{}\"", format))?;
+            let name = &rest[open + 1..open + close];
+            let field = Field::parse(name).ok_or_else(|| {
+                format!("unknown placeholder {{{}}} (expected seq, from, rtt, ttl, bytes or tos)", name)
+            })?;
+
+            segments.push(Segment::Placeholder(field));
+            rest = &rest[open + close + 1..];
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(ReplyTemplate { segments })
+    }
+
+    pub fn render(&self, reply: &Reply, ttl: i32) -> String {
+        let mut line = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => line.push_str(text),
+                Segment::Placeholder(Field::Sequence) => line.push_str(&reply.sequence.to_string()),
+                Segment::Placeholder(Field::From) => line.push_str(&reply.source.to_string()),
+                Segment::Placeholder(Field::Rtt) => line.push_str(&output::rtt_value(reply.rtt)),
+                Segment::Placeholder(Field::Ttl) => line.push_str(&ttl.to_string()),
+                Segment::Placeholder(Field::Bytes) => line.push_str(&reply.bytes.to_string()),
+                Segment::Placeholder(Field::Tos) => match reply.tos {
+                    Some(tos) => line.push_str(&format!("0x{:02x}", tos)),
+                    None => line.push('-'),
+                },
+            }
+        }
+
+        line
+    }
+}
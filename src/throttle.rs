@@ -0,0 +1,72 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::{sweep, Options, PingStatistics};
+
+/// Loss below this percentage is not worth a second pass.
+const NOTABLE_LOSS: f64 = 10.0;
+
+/// The slower pass sends at a quarter of the run's rate, and at most one
+/// probe per second.
+const SLOWDOWN: u32 = 4;
+const SLOW_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Probes in the slower pass; enough to tell 40% loss from none.
+const SLOW_PROBES: u32 = 10;
+
+/// Re-probes `target` at a lower rate after a run with notable loss and
+/// reports whether the loss goes away, which points at the target limiting
+/// how many ICMP echo requests it answers rather than at the path dropping
+/// packets (`--detect-ratelimit`).
+pub fn detect(target: IpAddr, options: &Options, stats: &PingStatistics) {
+    let loss = stats.loss_percent();
+    if stats.sent == 0 || loss < NOTABLE_LOSS {
+        return;
+    }
+    if stats.received == 0 {
+        say!("\n{} answered nothing, so the loss cannot be rate limiting alone.", target);
+        return;
+    }
+
+    let slow = Options {
+        interval: (options.interval * SLOWDOWN).max(SLOW_MIN_INTERVAL),
+        count: Some(options.count.map_or(SLOW_PROBES, |count| count.min(SLOW_PROBES))),
+        ..options.clone()
+    };
+    say!(
+        "\nChecking {} for ICMP rate limiting: {} probes at {}...",
+        target,
+        slow.count.unwrap_or(SLOW_PROBES),
+        pps(slow.interval)
+    );
+    let (slow_stats, _) = sweep::probe_host(target, &slow, std::process::id() as u16, None);
+    let slow_loss = slow_stats.loss_percent();
+
+    if slow_loss <= loss / 2.0 {
+        say!(
+            "{} appears to rate-limit ICMP (loss {:.0}%@{} vs {:.0}%@{})",
+            target,
+            loss,
+            pps(options.interval),
+            slow_loss,
+            pps(slow.interval)
+        );
+    } else {
+        say!(
+            "{} does not look rate limited: loss stayed at {:.0}% when slowed from {} to {}",
+            target,
+            slow_loss,
+            pps(options.interval),
+            pps(slow.interval)
+        );
+    }
+}
+
+fn pps(interval: Duration) -> String {
+    let rate = 1.0 / interval.as_secs_f64().max(f64::EPSILON);
+    if (rate - rate.round()).abs() < 0.05 {
+        format!("{:.0}pps", rate)
+    } else {
+        format!("{:.2}pps", rate)
+    }
+}
@@ -0,0 +1,165 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::{
+    count_down, describe_count, is_timeout, json, ndjson, next_sequence, output, pacing_start, print_histogram,
+    print_statistics, report, sleep_until_next_probe, webhook, Options, PingStatistics, Reply,
+};
+
+/// What `--probe` sends to test reachability.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Probe {
+    Icmp,
+    Udp(u16),
+    Tcp(u16),
+}
+
+impl Probe {
+    /// Parses `icmp`, `udp:<port>` or `tcp:<port>`.
+    pub fn parse(value: &str) -> Option<Probe> {
+        if value.eq_ignore_ascii_case("icmp") {
+            return Some(Probe::Icmp);
+        }
+        let (protocol, port) = value.split_once(':')?;
+        let port = port.parse().ok().filter(|port| *port != 0)?;
+        match protocol.to_ascii_lowercase().as_str() {
+            "udp" => Some(Probe::Udp(port)),
+            "tcp" => Some(Probe::Tcp(port)),
+            _ => None,
+        }
+    }
+}
+
+/// How a single UDP or TCP probe ended.
+enum Outcome {
+    /// Something answered on the port: a UDP datagram or a completed handshake.
+    Open,
+    /// The host answered with a TCP reset or an ICMP port unreachable, so it
+    /// is up even though nothing listens on the port.
+    Closed,
+    Failed(io::Error),
+}
+
+/// A root NS query, so that `udp:53` gets an answer from a DNS server
+/// instead of being dropped as garbage.
+const DNS_QUERY: [u8; 17] = [0x52, 0x49, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1];
+
+/// Probes `target` over UDP or TCP instead of ICMP echo, `-c` times at the
+/// `-i` interval. TCP timings are the connect handshake (SYN to SYN-ACK or
+/// RST); UDP timings are until a datagram or port-unreachable comes back.
+pub fn run_transport(target: IpAddr, probe: Probe, options: &Options) -> PingStatistics {
+    let (name, port) = match probe {
+        Probe::Udp(port) => ("udp", port),
+        Probe::Tcp(port) => ("tcp", port),
+        Probe::Icmp => unreachable!("ICMP probes are sent by run_ring"),
+    };
+    let address = SocketAddr::new(target, port);
+    let timeout = Duration::from_millis(options.timeout.max(1) as u64);
+    log_info!("starting {} run against {} (count={})", name, address, describe_count(options.count));
+
+    let mut stats = PingStatistics::for_run(options);
+    let started = Instant::now();
+    let mut remaining = options.count;
+    let mut sequence: u16 = 0;
+
+    while remaining != Some(0) {
+        sequence = next_sequence(sequence, options);
+        let start = Instant::now();
+        let outcome = match probe {
+            Probe::Tcp(_) => probe_tcp(address, timeout),
+            _ => probe_udp(address, options.packet_size, timeout),
+        };
+        let rtt = start.elapsed();
+        let lost = matches!(outcome, Outcome::Failed(_));
+        stats.sent += 1;
+
+        match outcome {
+            Outcome::Open | Outcome::Closed => {
+                stats.record_reply(rtt);
+                let state = match outcome {
+                    Outcome::Open => "open",
+                    _ => "closed",
+                };
+                say!("Reply from {}: {} port {} time={}", address, name, state, output::rtt(rtt));
+                let reply = Reply {
+                    sequence,
+                    source: target,
+                    bytes: 0,
+                    rtt,
+                    tos: None,
+                    ttl: None,
+                    payload_crc: None,
+                    rx_drops: None,
+                    truncated: false,
+                    delivery: None,
+                    send: None,
+                };
+                report::reply(target, &reply);
+                ndjson::write(&json::reply(target, &reply, None));
+            }
+            Outcome::Failed(e) => {
+                log_debug!("{} probe to {} failed: {}", name, address, e);
+                match probe {
+                    // Silence is all an open UDP port that does not answer gives back.
+                    Probe::Udp(_) if is_timeout(&e) => say!("No response from {} (port open or filtered).", address),
+                    _ if is_timeout(&e) => say!("Request timed out."),
+                    _ => say!("Probe to {} failed: {}", address, e),
+                }
+                report::timeout(target, sequence);
+                if is_timeout(&e) {
+                    ndjson::write(&json::timeout(target, sequence));
+                } else {
+                    ndjson::write(&json::error("probe", target, &e).int("seq", sequence));
+                }
+            }
+        }
+
+        count_down(&mut remaining);
+        if remaining != Some(0) {
+            sleep_until_next_probe(pacing_start(start, lost, options), options.interval);
+        }
+    }
+
+    stats.elapsed = started.elapsed();
+    log_info!("{} run against {} finished: sent={} received={}", name, address, stats.sent, stats.received);
+
+    report::summary(target, &stats);
+    ndjson::write(&json::summary(target, &stats));
+    webhook::post(&json::summary(target, &stats));
+    print_statistics(target, &stats);
+    print_histogram(&stats, options);
+    stats
+}
+
+fn probe_tcp(address: SocketAddr, timeout: Duration) -> Outcome {
+    match TcpStream::connect_timeout(&address, timeout) {
+        Ok(_) => Outcome::Open,
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Outcome::Closed,
+        Err(e) => Outcome::Failed(e),
+    }
+}
+
+fn probe_udp(address: SocketAddr, payload_size: usize, timeout: Duration) -> Outcome {
+    let local = match address {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    };
+    let payload = match address.port() {
+        53 => DNS_QUERY.to_vec(),
+        _ => vec![0u8; payload_size],
+    };
+
+    // A connected socket is told about ICMP port unreachable as ECONNREFUSED.
+    let result = UdpSocket::bind(local).and_then(|socket| {
+        socket.connect(address)?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.send(&payload)?;
+        socket.recv(&mut [0u8; 1500])
+    });
+    match result {
+        Ok(_) => Outcome::Open,
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Outcome::Closed,
+        Err(e) => Outcome::Failed(e),
+    }
+}
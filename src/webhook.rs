@@ -0,0 +1,96 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::json::Object;
+
+/// Connect, write and read limit for one POST, so a dead endpoint cannot
+/// stall the probe loop for long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+static ENDPOINT: OnceLock<Endpoint> = OnceLock::new();
+
+/// Sets the URL that `post` sends to. Only plain `http://` is supported.
+pub fn configure(url: &str) -> Result<(), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("{} is not an http:// URL", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    // IPv6 literals are bracketed so their colons are not read as a port.
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']').ok_or_else(|| format!("unclosed [ in {}", url))?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse::<u16>().map_err(|_| format!("invalid port in {}", url))?,
+        None => 80,
+    };
+    if host.is_empty() {
+        return Err(format!("missing host in {}", url));
+    }
+
+    let _ = ENDPOINT.set(Endpoint { host: host.to_string(), port, path: path.to_string() });
+    Ok(())
+}
+
+/// POSTs `record` as JSON if a webhook is configured. Failures are logged
+/// and otherwise ignored so monitoring carries on.
+pub fn post(record: &Object) {
+    let Some(endpoint) = ENDPOINT.get() else {
+        return;
+    };
+
+    match send(endpoint, &record.to_line()) {
+        Ok(status) if (200..300).contains(&status) => log_debug!("webhook accepted status update ({})", status),
+        Ok(status) => log_warn!("webhook {}:{}{} answered HTTP {}", endpoint.host, endpoint.port, endpoint.path, status),
+        Err(e) => log_warn!("webhook {}:{}{} unreachable: {}", endpoint.host, endpoint.port, endpoint.path, e),
+    }
+}
+
+fn send(endpoint: &Endpoint, body: &str) -> io::Result<u16> {
+    let address = (endpoint.host.as_str(), endpoint.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for webhook host"))?;
+
+    let mut stream = TcpStream::connect_timeout(&address, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let host_header = match endpoint.host.contains(':') {
+        true => format!("[{}]:{}", endpoint.host, endpoint.port),
+        false => format!("{}:{}", endpoint.host, endpoint.port),
+    };
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint.path,
+        host_header,
+        body.len(),
+        body
+    )?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP status line"))
+}
@@ -0,0 +1,343 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use socket2::{SockAddr, Socket};
+
+use crate::packet::{parse_icmp_error, Endian, Family};
+use crate::{
+    append_rtt_change, asn, base_identifier, bind_identifier, catch_interrupt, check_reply_headers,
+    collect_late_replies, count_down, create_icmp_packet, create_socket, describe_count, echo_reply_type, format_reply,
+    highlight, initial_sequence, json, ndjson, next_sequence, note_path_mtu, output, parse_received,
+    payload_crc_matches, payload_rng, pcap, print_histogram, print_statistics, read_error_queue, receive_before, report,
+    report_error, report_permission_denied, short_reply, show_local_port, ttl_warning, webhook, ExitReason, Options,
+    PingStatistics, ProbeError, Reply, SocketKind, GRACE_TRACKED, INTERRUPTED,
+};
+
+/// How many probes written off by `--strict-reply-order` are remembered, so
+/// their late replies can be recognised as duplicates.
+const WRITTEN_OFF_LIMIT: usize = 256;
+
+/// A probe that has been sent and is waiting for its reply.
+struct InFlight {
+    sequence: u16,
+    sent: Instant,
+    /// How long the send call took.
+    send: Duration,
+    /// `--retries` already spent on this probe.
+    attempts: u32,
+}
+
+/// Pings `target` with up to `window` probes outstanding at once.
+///
+/// Probes still go out every `-i` interval, but a slow reply no longer holds
+/// back the next one. Replies are matched to their probe by sequence number
+/// and timed against the send time recorded for it, so they may arrive and
+/// be printed out of order. A probe is counted as lost once `-w` has passed
+/// without its reply.
+///
+/// With `--strict-reply-order`, a reply that overtakes earlier probes writes
+/// them off as lost straight away, and their replies count as duplicates if
+/// they turn up later.
+///
+/// A probe that times out is sent again under `--retries`, and takes its
+/// place at the back of the window. `--abort-on-max-rtt` stops sending but
+/// still waits for the probes already out, so they are not counted as lost.
+pub fn run_windowed(target: IpAddr, options: &Options, window: usize) -> PingStatistics {
+    let &Options { mut count, packet_size, .. } = options;
+    let (socket, kind) = match create_socket(target, options) {
+        Ok(created) => created,
+        Err(e) => {
+            log_error!("failed to create socket for {}: {}", target, e);
+            report_error(json::error("socket", target, &e), format!("Failed to create socket: {}", e));
+            std::process::exit(1);
+        }
+    };
+    bind_identifier(&socket, kind, target, options);
+    if options.show_local_port {
+        show_local_port(&socket, kind, target, options);
+    }
+    log_info!("starting windowed run against {} (window={}, count={})", target, window, describe_count(count));
+
+    let dest_addr = socket2::SockAddr::from(SocketAddr::new(target, 0));
+    let timeout = Duration::from_millis(options.timeout as u64);
+    let identifier = base_identifier(options);
+    let mut payload_rng = payload_rng(options);
+    let mut stats = PingStatistics::for_run(options);
+    let started = Instant::now();
+    let mut in_flight: VecDeque<InFlight> = VecDeque::with_capacity(window);
+    let mut written_off: VecDeque<u16> = VecDeque::new();
+    // Probes that timed out, whose replies `--grace` still waits for.
+    let mut timed_out: VecDeque<u16> = VecDeque::new();
+    let mut sequence = initial_sequence(options);
+    let mut next_send = Instant::now();
+    let mut buffer = vec![0u8; options.recv_buffer];
+    // RTT of the reply that arrived last, which `--show-delta` and `--trend`
+    // compare with.
+    let mut previous_rtt: Option<Duration> = None;
+
+    if options.exit_reason {
+        catch_interrupt();
+    }
+
+    'run: loop {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            stats.exit_reason = ExitReason::Interrupted;
+            break;
+        }
+        let more_to_send = count != Some(0);
+        let now = Instant::now();
+
+        if more_to_send && in_flight.len() < window && now >= next_send {
+            sequence = next_sequence(sequence, options);
+            let packet = create_icmp_packet(packet_size, target, identifier, sequence, payload_rng.as_mut(), options.payload_crc);
+            let sent = Instant::now();
+            match send_probe(&socket, &dest_addr, &packet, sequence, 0) {
+                Ok(probe) => in_flight.push_back(probe),
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    report_permission_denied(target, &e);
+                    stats.exit_reason = ExitReason::PermissionDenied;
+                    break;
+                }
+                Err(e) => {
+                    log_warn!("socket error while probing {}: {}", target, e);
+                    say!("Request timed out.");
+                    report::timeout(target, sequence);
+                    ndjson::write(&json::error("probe", target, &e).int("seq", sequence));
+                }
+            }
+            stats.sent += 1;
+            next_send = sent + options.interval;
+            count_down(&mut count);
+            continue;
+        }
+
+        while in_flight.front().is_some_and(|probe| now >= probe.sent + timeout) {
+            let probe = in_flight.pop_front().unwrap();
+            if probe.attempts < options.retries && stats.exit_reason == ExitReason::CountReached {
+                // Retransmissions use a fresh sequence number so a late reply
+                // to the earlier attempt is not mistaken for this one.
+                let attempts = probe.attempts + 1;
+                sequence = next_sequence(sequence, options);
+                log_info!("retrying probe to {} with seq={} (attempt {} of {})", target, sequence, attempts, options.retries);
+                if attempts == 1 {
+                    stats.retried += 1;
+                }
+                let packet = create_icmp_packet(packet_size, target, identifier, sequence, payload_rng.as_mut(), options.payload_crc);
+                match send_probe(&socket, &dest_addr, &packet, sequence, attempts) {
+                    Ok(retry) => {
+                        in_flight.push_back(retry);
+                        continue;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                        report_permission_denied(target, &e);
+                        stats.exit_reason = ExitReason::PermissionDenied;
+                        break 'run;
+                    }
+                    Err(e) => log_warn!("socket error while retrying the probe to {}: {}", target, e),
+                }
+            }
+            log_debug!("probe to {} with seq={} timed out", target, probe.sequence);
+            say!("Request timed out. (seq={})", probe.sequence);
+            report::timeout(target, probe.sequence);
+            ndjson::write(&json::timeout(target, probe.sequence));
+            if options.grace.is_some() {
+                if timed_out.len() == GRACE_TRACKED {
+                    timed_out.pop_front();
+                }
+                timed_out.push_back(probe.sequence);
+            }
+        }
+
+        if !more_to_send && in_flight.is_empty() {
+            break;
+        }
+
+        // Wake for whichever comes first: the oldest probe expiring or the
+        // next send slot opening up.
+        let mut deadline = in_flight.front().map_or(next_send, |probe| probe.sent + timeout);
+        if more_to_send && in_flight.len() < window {
+            deadline = deadline.min(next_send);
+        }
+
+        let received = match receive_before(&socket, &mut buffer, deadline) {
+            Ok(Some(received)) => received,
+            Ok(None) => continue,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                report_permission_denied(target, &e);
+                stats.exit_reason = ExitReason::PermissionDenied;
+                break;
+            }
+            Err(e) => {
+                let queued = read_error_queue(&socket, target);
+                if queued.is_empty() {
+                    log_warn!("socket error while probing {}: {}", target, e);
+                }
+                for entry in queued {
+                    let same_probe = |probe: &InFlight| {
+                        probe.sequence == entry.sequence && (kind == SocketKind::Dgram || entry.identifier == identifier)
+                    };
+                    if let Some(position) = in_flight.iter().position(same_probe) {
+                        in_flight.remove(position);
+                        let error = ProbeError { error: entry.error, from: entry.from.unwrap_or(target), mtu: entry.mtu };
+                        report_failure(target, &mut stats, entry.sequence, &error);
+                    }
+                }
+                continue;
+            }
+        };
+        let arrived = Instant::now();
+
+        let Some(icmp) = parse_received(&buffer[..received.len], kind, target) else {
+            continue;
+        };
+        if kind == SocketKind::Raw {
+            if let Some(quoted) = parse_icmp_error(&icmp, Family::of(target), Endian::current()) {
+                let position = in_flight
+                    .iter()
+                    .position(|probe| quoted.identifier == identifier && probe.sequence == quoted.sequence);
+                if let Some(position) = position {
+                    in_flight.remove(position);
+                    let from = received.source.map_or(target, |source| source.ip());
+                    let error = ProbeError { error: quoted.error, from, mtu: quoted.mtu };
+                    report_failure(target, &mut stats, quoted.sequence, &error);
+                }
+                continue;
+            }
+        }
+        if icmp.icmp_type != echo_reply_type(target) || (kind == SocketKind::Raw && icmp.identifier != identifier) {
+            continue;
+        }
+        let Some(position) = in_flight.iter().position(|probe| probe.sequence == icmp.sequence) else {
+            if let Some(index) = written_off.iter().position(|sequence| *sequence == icmp.sequence) {
+                written_off.remove(index);
+                stats.duplicates += 1;
+                say!("Late reply for seq={}, already counted as lost (duplicate)", icmp.sequence);
+            } else {
+                log_debug!("ignoring reply from {} with seq={} outside the window", target, icmp.sequence);
+            }
+            continue;
+        };
+
+        // `in_flight` is in send order, so anything ahead of this probe was
+        // sent earlier and is still unanswered.
+        if position > 0 {
+            stats.reordered += 1;
+            log_debug!("reply from {} with seq={} overtook {} earlier probe(s)", target, icmp.sequence, position);
+            if options.strict_reply_order {
+                for skipped in in_flight.drain(..position) {
+                    say!("Request lost to reordering. (seq={})", skipped.sequence);
+                    report::timeout(target, skipped.sequence);
+                    ndjson::write(&json::timeout(target, skipped.sequence));
+                    if written_off.len() == WRITTEN_OFF_LIMIT {
+                        written_off.pop_front();
+                    }
+                    written_off.push_back(skipped.sequence);
+                }
+            }
+        }
+        let position = if options.strict_reply_order { 0 } else { position };
+        let probe = in_flight.remove(position).unwrap();
+        if probe.attempts > 0 {
+            stats.recovered += 1;
+        }
+
+        let reply = Reply {
+            sequence: icmp.sequence,
+            source: received.source.map_or(target, |source| source.ip()),
+            bytes: icmp.payload.len(),
+            rtt: arrived - probe.sent,
+            tos: icmp.tos.or(received.tos),
+            ttl: icmp.ttl.or(received.ttl),
+            payload_crc: (options.payload_crc && !received.truncated).then(|| payload_crc_matches(icmp.payload, packet_size)),
+            rx_drops: received.rx_drops,
+            truncated: received.truncated,
+            delivery: received.delivery,
+            send: Some(probe.send),
+        };
+        stats.record_reply(reply.rtt);
+        stats.record_drops(&reply);
+        stats.record_overhead(&reply);
+        if reply.source != target {
+            stats.other_responders += 1;
+        }
+
+        // Replies can overtake each other, so the default line names its probe.
+        let mut line = format_reply(target, &reply, options);
+        if options.format.is_none() {
+            line.push_str(&format!(" seq={}", reply.sequence));
+        }
+        append_rtt_change(&mut line, previous_rtt, reply.rtt, options);
+        previous_rtt = Some(reply.rtt);
+        if reply.payload_crc == Some(false) {
+            stats.crc_mismatches += 1;
+            line = highlight(&format!("{} [payload CRC mismatch]", line));
+        }
+        if reply.truncated {
+            stats.truncated += 1;
+            line = highlight(&format!("{} [truncated]", line));
+        }
+        let short = short_reply(&reply, options);
+        if let Some(expected) = short {
+            stats.short_replies += 1;
+            line = highlight(&format!("{} [short reply: {} of {} bytes]", line, reply.bytes, expected));
+        }
+        if let Some(max_rtt) = options.max_rtt.filter(|max_rtt| reply.rtt > *max_rtt) {
+            stats.rtt_violations += 1;
+            line = highlight(&format!("{} [exceeds {}]", line, output::rtt(max_rtt)));
+            if options.abort_on_max_rtt && stats.exit_reason != ExitReason::RttLimit {
+                log_warn!("aborting run against {} after a reply exceeded the RTT limit", target);
+                stats.exit_reason = ExitReason::RttLimit;
+                count = Some(0);
+            }
+        }
+        say!("{}", line);
+        check_reply_headers(&mut stats, &reply, options);
+        report::reply(target, &reply);
+        let mut record = json::reply(target, &reply, ttl_warning(&reply, options));
+        if let Some(expected) = short {
+            record = record.int("expected_bytes", expected as i64);
+        }
+        ndjson::write(&record);
+    }
+
+    stats.elapsed = started.elapsed();
+    if let Some(grace) = options.grace.filter(|_| stats.exit_reason.waits_for_stragglers()) {
+        let identifiers = VecDeque::from([identifier]);
+        stats.late_replies = collect_late_replies(&socket, kind, target, &identifiers, &mut timed_out, grace, options);
+    }
+
+    log_info!("windowed run against {} finished: sent={} received={}", target, stats.sent, stats.received);
+
+    report::summary(target, &stats);
+    ndjson::write(&json::summary(target, &stats));
+    webhook::post(&json::summary(target, &stats));
+    print_statistics(target, &stats);
+    print_histogram(&stats, options);
+    if options.exit_reason {
+        say!("exited: {}", stats.exit_reason.describe());
+    }
+    stats
+}
+
+/// Sends one echo request and returns it as waiting for its reply.
+fn send_probe(socket: &Socket, dest_addr: &SockAddr, packet: &[u8], sequence: u16, attempts: u32) -> io::Result<InFlight> {
+    let sent = Instant::now();
+    socket.send_to(packet, dest_addr)?;
+    let send = sent.elapsed();
+    pcap::sent(dest_addr, packet);
+    Ok(InFlight { sequence, sent, send, attempts })
+}
+
+/// Records a probe that was answered by an ICMP error.
+fn report_failure(target: IpAddr, stats: &mut PingStatistics, sequence: u16, error: &ProbeError) {
+    stats.record_error(error.error);
+    say!("Reply from {}{}: {}. (seq={})", error.from, asn::annotate(error.from), error.reason(), sequence);
+    if let Some(mtu) = error.mtu {
+        note_path_mtu(target, stats, mtu);
+    }
+    report::timeout(target, sequence);
+    ndjson::write(&json::icmp_error(target, sequence, error));
+}